@@ -0,0 +1,87 @@
+// times `execute` on a token with 5000 facts, comparing the current package
+// with a baseline one, like a build from before the switch from JSON strings
+// to serde-wasm-bindgen.
+//
+// build the packages for node first, the baseline from a checkout of the
+// commit to compare with:
+//
+//     cargo build --release --target wasm32-unknown-unknown
+//     wasm-bindgen --target nodejs --out-dir bench/pkg \
+//         target/wasm32-unknown-unknown/release/biscuit_component_wasm.wasm
+//     (same commands in the baseline checkout, to --out-dir bench/pkg-baseline)
+//     node --expose-gc bench/execute.js [runs] [package directory] [baseline package directory]
+//
+// without a baseline, only the current package is timed. The runs of the
+// packages alternate, so they share the same machine load
+
+const path = require("path");
+
+// the package logs every step to the console
+const print = console.log;
+for (const level of ["log", "debug", "info", "warn", "error"]) {
+  console[level] = () => {};
+}
+
+const runs = Number(process.argv[2] || 20);
+const directories = [process.argv[3] || path.join(__dirname, "pkg")];
+if (process.argv[4]) {
+  directories.push(process.argv[4]);
+}
+const packages = directories.map((directory) => ({
+  name: directory,
+  pkg: require(path.resolve(directory, "biscuit_component_wasm.js")),
+  times: [],
+}));
+
+const FACTS = 5000;
+
+const facts = [];
+for (let i = 0; i < FACTS; i++) {
+  facts.push(`right(#authority, "file${i}", #read);`);
+}
+
+const query = {
+  token_blocks: [facts.join("\n")],
+  verifier_code: "allow if right(#authority, \"file0\", #read);",
+  query_params: {},
+  verifier_limits: { max_facts: 2 * FACTS, max_time_millis: 10000 },
+};
+
+// the builds from before serde-wasm-bindgen return a JSON value that may
+// have to be parsed
+const execute = (pkg) => {
+  const result = pkg.execute(query);
+  return typeof result === "string" ? JSON.parse(result) : result;
+};
+
+for (const { name, pkg } of packages) {
+  // the first calls compile the wasm code
+  let result;
+  for (let i = 0; i < 3; i++) {
+    result = execute(pkg);
+  }
+
+  if (result.verifier_world.length < FACTS) {
+    throw new Error(`${name}: expected at least ${FACTS} facts in the world, got ${result.verifier_world.length}`);
+  }
+}
+
+for (let i = 0; i < runs; i++) {
+  for (const { pkg, times } of packages) {
+    // started with --expose-gc, the previous results are collected outside
+    // of the measure
+    if (global.gc) {
+      global.gc();
+    }
+    const start = process.hrtime.bigint();
+    execute(pkg);
+    times.push(Number(process.hrtime.bigint() - start) / 1e6);
+  }
+}
+
+print(`${FACTS} facts, ${runs} runs`);
+for (const { name, times } of packages) {
+  times.sort((a, b) => a - b);
+  const median = times[Math.floor(times.length / 2)];
+  print(`${name}: min ${times[0].toFixed(1)} ms, median ${median.toFixed(1)} ms`);
+}
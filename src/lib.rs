@@ -1,17 +1,28 @@
 use wasm_bindgen::prelude::*;
 use biscuit_auth::{
-    crypto::KeyPair,
+    crypto::{KeyPair, PrivateKey, PublicKey},
+    datalog::{Binary, SymbolTable},
     error,
-    parser::parse_source,
-    token::Biscuit,
+    format::{convert::proto_block_to_token_block, schema, SerializedBiscuit},
+    parser::{parse_source, SourceResult},
+    token::{default_symbol_table, Biscuit, Block as TokenBlock},
     token::builder,
     token::verifier::{Verifier, VerifierLimits},
 };
 use log::*;
 use nom::Offset;
+use prost::Message;
 use rand::prelude::*;
-use serde::{Serialize, Deserialize};
+use serde::{de::DeserializeOwned, Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::default::Default;
+use std::ops::Range;
+use std::sync::Once;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tsify::Tsify;
 
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
@@ -24,400 +35,4339 @@ extern "C" {
     fn log(s: &str);
 }
 
-#[derive(Serialize, Deserialize)]
+// the values are converted directly between JS and Rust, without going
+// through a JSON string. Maps become plain objects, as the TypeScript
+// declarations expect
+fn from_js<T: DeserializeOwned>(value: &JsValue) -> Result<T, String> {
+    serde_wasm_bindgen::from_value(value.clone()).map_err(|e| format!("invalid input: {}", e))
+}
+
+// if the value cannot be converted, an object with only an `error` field is
+// returned, so a bad result never panics the module
+fn to_js<T: Serialize, U: JsCast>(value: &T) -> U {
+    let value = value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .unwrap_or_else(|e| {
+            let object = js_sys::Object::new();
+            let _ = js_sys::Reflect::set(&object, &"error".into(), &format!("invalid output: {}", e).into());
+            object.into()
+        });
+
+    value.unchecked_into()
+}
+
+// TypeScript types of the exported functions' arguments and return values,
+// the interfaces themselves are generated from the serde types by Tsify
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "BiscuitQuery")]
+    pub type JsBiscuitQuery;
+    #[wasm_bindgen(typescript_type = "BiscuitResult")]
+    pub type JsBiscuitResult;
+    #[wasm_bindgen(typescript_type = "TokenResult")]
+    pub type JsTokenResult;
+    #[wasm_bindgen(typescript_type = "KeyPairResult")]
+    pub type JsKeyPairResult;
+    #[wasm_bindgen(typescript_type = "SignatureResult")]
+    pub type JsSignatureResult;
+    #[wasm_bindgen(typescript_type = "TokenDiff")]
+    pub type JsTokenDiff;
+    #[wasm_bindgen(typescript_type = "AuthorizerResult")]
+    pub type JsAuthorizerResult;
+    #[wasm_bindgen(typescript_type = "QueryResult")]
+    pub type JsQueryResult;
+    #[wasm_bindgen(typescript_type = "FormatResult")]
+    pub type JsFormatResult;
+    #[wasm_bindgen(typescript_type = "EvalResult")]
+    pub type JsEvalResult;
+    #[wasm_bindgen(typescript_type = "Record<string, TermValue>")]
+    pub type JsTermValues;
+    #[wasm_bindgen(typescript_type = "Editor[]")]
+    pub type JsEditors;
+    #[wasm_bindgen(typescript_type = "string[]")]
+    pub type JsStrings;
+}
+
+#[derive(Default, Serialize, Deserialize, Tsify)]
+#[serde(default)]
 struct BiscuitQuery {
     pub token_blocks: Vec<String>,
     pub verifier_code: Option<String>,
+    /// snippets of verifier code loaded one after the other, in place of
+    /// `verifier_code`. Each one gets its own editor in `verifier_editors`
+    pub verifier_blocks: Option<Vec<String>>,
     pub query: Option<String>,
+    pub queries: Option<Vec<NamedQuery>>,
+    /// values substituted for the variables of `query`
+    pub query_params: HashMap<String, TermValue>,
+    /// seeds the generated root key and the signatures, to get the same token
+    /// on every call. Without it, or with `root_private_key`, the randomness
+    /// comes from the system
+    pub root_seed: Option<u64>,
+    /// hex encoded private key signing the token, as returned by `generate_keypair`.
+    /// The signatures then always use system randomness: a nonce derived from
+    /// a known seed would reveal the private key. `root_seed`, `deterministic`
+    /// and `block_seed` are ignored
+    pub root_private_key: Option<String>,
+    /// derives the randomness of each appended block from its index only: the
+    /// block's temporary keypair and signature use an RNG seeded with
+    /// `seed_from_u64(block_index)`, the authority being block 0, so adding a
+    /// block does not change the bytes of the previous ones. The root key
+    /// still comes from `root_seed`
+    pub deterministic: Option<bool>,
+    /// seeds the temporary keypairs of the appended blocks separately from
+    /// the root key. With `deterministic`, each block is seeded with
+    /// `block_seed + block_index` instead of its index alone
+    pub block_seed: Option<u64>,
+    pub verifier_limits: Option<VerifierLimitsInput>,
+    /// seals the token after appending all the blocks, so it cannot be attenuated anymore
+    pub seal: Option<bool>,
+    /// also returns the token in `serialized_armored`
+    pub armored: Option<bool>,
+    /// secret used to sign the sealed token, empty if absent
+    pub seal_secret: Option<String>,
+    /// RFC 3339 date provided to the verifier as `time(#ambient, $date)`,
+    /// the current time is used if absent
+    pub current_time: Option<String>,
+    pub ambient: Option<AmbientContext>,
+    /// facts added to the verifier along with the ambient ones, built from
+    /// typed terms instead of datalog source
+    pub world_facts_json: Option<Vec<FactInput>>,
+    /// parallel to `token_blocks`: disabled blocks are left out of the token
+    /// but keep an empty editor. Blocks without an entry are enabled, and a
+    /// disabled authority block is generated empty
+    pub enabled: Option<Vec<bool>>,
+    /// indexes of verifier policies, in source order, that are parsed but not
+    /// added to the verifier. They are listed as "disabled" in `policies`
+    pub disabled_policies: Option<Vec<usize>>,
+    /// only returns the facts of `verifier_world` with one of these names
+    pub world_filter: Option<Vec<String>>,
+    /// only returns the first facts of `verifier_world`, which is sorted so the
+    /// same ones are kept from one run to the next
+    pub world_max_facts: Option<usize>,
+    /// also prints the token without resolving its symbols in `token_content_raw`
+    pub print_raw: Option<bool>,
+    /// verifiers run one after the other against the token, after
+    /// `verifier_code`, each with its own code and ambient data
+    pub scenarios: Option<Vec<VerifierScenario>>,
+    /// stops at the first block that cannot be parsed, setting `error` and
+    /// returning the editors up to that block. The verifier code is still
+    /// reported in `verifier_editor`, but its parse errors also set `error`
+    pub fail_fast: Option<bool>,
+    /// maximum length in bytes of all the datalog sources together, blocks,
+    /// verifier and queries. Defaults to `DEFAULT_MAX_INPUT_SIZE`
+    pub max_input_size: Option<usize>,
 }
 
-#[derive(Default, Serialize, Deserialize)]
-struct BiscuitResult {
-    pub token_blocks: Vec<Editor>,
-    pub token_content: String,
-    pub verifier_editor: Option<Editor>,
-    pub verifier_result: Option<String>,
-    pub verifier_world: Vec<Fact>,
-    pub query_result: Vec<Fact>,
+/// 256 KiB, far more than any hand written token or verifier
+const DEFAULT_MAX_INPUT_SIZE: usize = 256 * 1024;
+
+impl BiscuitQuery {
+    fn block_enabled(&self, index: usize) -> bool {
+        self.enabled.as_ref().and_then(|enabled| enabled.get(index)).cloned().unwrap_or(true)
+    }
+
+    // total length of the datalog sources
+    fn input_size(&self) -> usize {
+        self.token_blocks.iter().map(|code| code.len()).sum::<usize>()
+            + self.verifier_code.as_ref().map(|code| code.len()).unwrap_or(0)
+            + self.verifier_blocks.iter().flatten().map(|code| code.len()).sum::<usize>()
+            + self.query.as_ref().map(|query| query.len()).unwrap_or(0)
+            + self.queries.iter().flatten().map(|query| query.datalog.len()).sum::<usize>()
+            + self.scenarios.iter().flatten().map(|scenario| scenario.verifier_code.len()).sum::<usize>()
+    }
 }
 
-#[derive(Default, Serialize, Deserialize)]
-struct Editor {
-    pub errors: Vec<ParseError>,
-    pub markers: Vec<Marker>,
+/// a verifier to run against the token of the query, identified by its name
+/// in `scenario_results`
+#[derive(Serialize, Deserialize, Tsify)]
+struct VerifierScenario {
+    pub name: String,
+    pub verifier_code: String,
+    #[serde(default)]
+    pub ambient: AmbientContext,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Marker {
-    pub ok: bool,
-    pub position: SourcePosition,
+/// a query run against the verifier's world, identified by its name in the results
+#[derive(Serialize, Deserialize, Tsify)]
+struct NamedQuery {
+    pub name: String,
+    pub datalog: String,
+    /// values substituted for the variables of `datalog`
+    #[serde(default)]
+    pub params: HashMap<String, TermValue>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct ParseError {
-    pub message: String,
-    pub position: SourcePosition,
+/// a query parameter, using the same `type` names as the terms in the results
+#[derive(Serialize, Deserialize, Tsify)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+enum TermValue {
+    String(String),
+    /// a number, or a string for the integers beyond `Number.MAX_SAFE_INTEGER`
+    Int(
+        #[serde(serialize_with = "serialize_int", deserialize_with = "deserialize_int")]
+        #[tsify(type = "number | string")]
+        i64,
+    ),
+    Bool(bool),
+    /// RFC 3339 date
+    Date(String),
+    /// hex encoded, with or without a `hex:` prefix
+    Bytes(String),
+    /// written without the `#` prefix
+    Symbol(String),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct SourcePosition {
-    pub line_start: usize,
-    pub column_start: usize,
-    pub line_end: usize,
-    pub column_end: usize,
+impl TermValue {
+    fn to_term(&self) -> Result<builder::Term, String> {
+        Ok(match self {
+            TermValue::String(s) => builder::Term::Str(s.clone()),
+            TermValue::Int(i) => builder::Term::Integer(*i),
+            TermValue::Bool(b) => builder::Term::Bool(*b),
+            TermValue::Date(d) => {
+                let date = chrono::DateTime::parse_from_rfc3339(d.trim())
+                    .map_err(|e| format!("invalid date \"{}\": {}", d, e))?;
+                builder::Term::Date(date.timestamp() as u64)
+            },
+            TermValue::Bytes(b) => {
+                let bytes = hex::decode(b.trim().trim_start_matches("hex:"))
+                    .map_err(|e| format!("invalid bytes \"{}\": {}", b, e))?;
+                builder::Term::Bytes(bytes)
+            },
+            TermValue::Symbol(s) => builder::Term::Symbol(s.clone()),
+        })
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct Fact {
+/// a fact given as a predicate name and typed terms, like
+/// `{ name: "resource", terms: [{ type: "symbol", value: "ambient" }, ...] }`
+#[derive(Serialize, Deserialize, Tsify)]
+struct FactInput {
     pub name: String,
-    pub terms: Vec<String>,
+    pub terms: Vec<TermValue>,
 }
 
-#[wasm_bindgen]
-pub fn execute(query: &JsValue) -> JsValue {
-    let query: BiscuitQuery = query.into_serde().unwrap();
-
-    let result = execute_inner(query);
-
-    JsValue::from_serde(&result).unwrap()
+impl FactInput {
+    fn to_fact(&self) -> Result<builder::Fact, String> {
+        let terms = self.terms.iter().map(TermValue::to_term).collect::<Result<Vec<_>, _>>()?;
+        Ok(builder::fact(&self.name, &terms))
+    }
 }
 
-fn execute_inner(query: BiscuitQuery) -> BiscuitResult {
-    let mut biscuit_result = BiscuitResult::default();
-
-    info!("will generate token");
-
-    let mut rng: StdRng = SeedableRng::seed_from_u64(0);
-    let root = KeyPair::new_with_rng(&mut rng);
-
-    let mut builder = Biscuit::builder(&root);
-
-    let mut authority = Block::default();
-    let mut blocks = Vec::new();
-
-    let mut token_opt = None;
+/// ambient data provided by the verifier, like a service would do for a request
+#[derive(Default, Serialize, Deserialize, Tsify)]
+#[serde(default)]
+struct AmbientContext {
+    /// added as `resource(#ambient, $resource)`
+    pub resource: Option<String>,
+    /// added as `operation(#ambient, #operation)`
+    pub operation: Option<String>,
+    /// facts in Datalog syntax, like `source_ip(#ambient, "127.0.0.1")`
+    pub extra_facts: Vec<String>,
+}
 
-    if !query.token_blocks.is_empty() {
-        let mut authority_editor = Editor::default();
+#[derive(Clone, Default, Serialize, Deserialize, Tsify)]
+#[serde(default)]
+struct VerifierLimitsInput {
+    pub max_facts: Option<u32>,
+    pub max_iterations: Option<u32>,
+    /// 0 disables the time limit
+    pub max_time_millis: Option<u64>,
+}
 
-        match parse_source(&query.token_blocks[0]) {
-            Err(errors) => {
-                error!("error: {:?}", errors);
-                authority_editor.errors = get_parse_errors(&query.token_blocks[0], errors);
-            },
-            Ok((_, authority_parsed)) => {
-                for (_, fact) in authority_parsed.facts.iter() {
-                    builder.add_authority_fact(fact.clone()).unwrap();
-                }
+/// time budget of the verifier when the query does not specify one
+const DEFAULT_MAX_TIME_MILLIS: u64 = 2_000;
+/// upper bound on the requested time budget, to avoid hanging the page
+const MAX_TIME_MILLIS: u64 = 10_000;
+/// time budget used when `max_time_millis` is 0. The engine adds it to the
+/// current instant, so `Duration::MAX` would overflow
+const NO_TIME_LIMIT: Duration = Duration::from_secs(u32::MAX as u64);
 
-                for (_, rule) in authority_parsed.rules.iter() {
-                    builder.add_authority_rule(rule.clone()).unwrap();
-                }
+impl VerifierLimitsInput {
+    fn to_limits(&self) -> VerifierLimits {
+        let defaults = VerifierLimits::default();
+        // 0 disables the time limit, for trusted programs that are already
+        // bounded by max_iterations or max_facts
+        let max_time = match self.max_time_millis.unwrap_or(DEFAULT_MAX_TIME_MILLIS) {
+            0 => NO_TIME_LIMIT,
+            millis => Duration::from_millis(millis.min(MAX_TIME_MILLIS)),
+        };
 
-                for (i, check) in authority_parsed.checks.iter() {
-                    builder.add_authority_check(check.clone()).unwrap();
-                    let position = get_position(&query.token_blocks[0], i);
-                    authority.checks.push((position, true));
-                }
-            }
+        VerifierLimits {
+            max_facts: self.max_facts.unwrap_or(defaults.max_facts),
+            max_iterations: self.max_iterations.unwrap_or(defaults.max_iterations),
+            max_time,
         }
+    }
+}
 
-        biscuit_result.token_blocks.push(authority_editor);
+#[derive(Default, Serialize, Deserialize, Tsify)]
+struct BiscuitResult {
+    pub token_blocks: Vec<Editor>,
+    pub token_content: String,
+    /// facts, rules and checks of each block as structured data
+    pub token_json: Option<TokenJson>,
+    /// blocks printed with symbol indexes, set if `print_raw` is true
+    pub token_content_raw: Option<String>,
+    /// datalog source of each block as printed back from the token, authority first
+    pub token_blocks_text: Vec<String>,
+    /// number of blocks in the token, authority included. Disabled and empty
+    /// blocks are not in the token, so this can be less than the editors
+    pub block_count: usize,
+    /// true if the token has blocks after the authority block
+    pub is_attenuated: bool,
+    /// hex encoding of the 32 bytes compressed root public key, as accepted by
+    /// `PublicKey::from_bytes`. Empty if no token was generated or loaded
+    pub root_public_key: String,
+    /// hex encoded revocation identifiers, one per block, authority first
+    pub revocation_ids: Vec<String>,
+    /// format version of the blocks, if they all use the same one
+    pub token_version: Option<u32>,
+    /// format version of each block, authority first
+    pub block_versions: Vec<u32>,
+    /// the token's symbol table, in index order
+    pub symbols: Vec<String>,
+    /// the symbols of `symbols` that are not in the default table, so they are
+    /// stored in the token
+    pub custom_symbols: Vec<String>,
+    /// size in bytes of the serialized token, sealed if `sealed` is true
+    pub token_size: usize,
+    /// RFC 3339 date after which the token's checks fail, the earliest one if
+    /// several checks limit the time. This is a guess from the usual expiry
+    /// pattern, `check if time(#ambient, $t), $t <= 2022-01-01T00:00:00Z`, with
+    /// `<`, `<=` or the date on the left: other ways to limit the time are
+    /// not recognized
+    pub expires_at: Option<String>,
+    /// hex encoded public keys of the signature chain: the root key for the
+    /// authority block, then the temporary key generated for each block.
+    /// Empty for tokens loaded in sealed form
+    pub block_keys: Vec<String>,
+    /// size in bytes of each serialized block, authority first
+    pub block_sizes: Vec<usize>,
+    /// index of the root public key that validated the token's signature,
+    /// when verifying a token against a list of candidate keys
+    pub root_key_index: Option<usize>,
+    /// URL safe base64 encoding of the token, as expected by `Biscuit::from_base64`
+    pub serialized: Option<String>,
+    /// the same bytes as `serialized`, hex encoded
+    pub serialized_hex: Option<String>,
+    /// `serialized` cut in lines of 64 characters between `-----BEGIN BISCUIT-----`
+    /// and `-----END BISCUIT-----`, set if `armored` is true. The functions
+    /// loading a base64 token accept this format too
+    pub serialized_armored: Option<String>,
+    /// hex encoded SHA-256 of the serialized token, sealed if `sealed` is
+    /// true, the same for tokens with the same bytes whatever their base64
+    /// encoding
+    pub token_fingerprint: Option<String>,
+    /// true if `serialized` contains a sealed token
+    pub sealed: bool,
+    pub verifier_editor: Option<Editor>,
+    /// the verifier editor split by snippet when `verifier_blocks` is used,
+    /// with positions relative to each snippet. Other positions refer to the
+    /// snippets joined in `verifier_code`
+    pub verifier_editors: Vec<Editor>,
+    /// the verifier code that was run, only set for `verifier_blocks`
+    pub verifier_code: Option<String>,
+    /// hex encoded public keys the verifier trusts. biscuit-auth 1.x has no
+    /// third-party blocks, so this is only the root key the token was
+    /// verified with, or empty for a verifier without token
+    pub verifier_trusted_keys: Vec<String>,
+    pub verifier_result: Option<VerifierResult>,
+    pub verifier_errors: Vec<VerifierError>,
+    /// policy that decided the verification result, if any
+    pub matched_policy: Option<MatchedPolicy>,
+    /// every check of the blocks and the verifier. Tokens loaded with
+    /// `verify_token` have no source, so only the verifier's checks are listed
+    pub checks: Vec<CheckResult>,
+    /// every policy of the verifier, in evaluation order
+    pub policies: Vec<PolicyEvaluation>,
+    /// wall-clock time spent in the verifier, to compare against `max_time_millis`
+    pub verifier_duration_millis: f64,
+    /// number of iterations of the datalog engine, to compare against
+    /// `max_iterations`. Always None for now: biscuit-auth runs the world
+    /// internally and does not report how many iterations it took
+    pub verifier_iterations: Option<u64>,
+    pub verifier_world: Vec<Fact>,
+    /// true if `verifier_world` was cut to `world_max_facts`
+    pub world_truncated: bool,
+    /// number of facts in `verifier_world` after `world_filter` but before truncation
+    pub world_total_facts: usize,
+    pub verifier_world_rules: Vec<String>,
+    pub verifier_world_checks: Vec<String>,
+    /// the whole world (facts, rules, checks and policies) as datalog that can
+    /// be pasted back in the verifier editor to reproduce its state
+    pub verifier_world_code: Option<String>,
+    /// URL safe base64 encoding of the verifier as saved by biscuit-auth after
+    /// verification, token checks and ambient facts included. It can be run
+    /// again with `restore_verifier_snapshot`. Limits are not part of it
+    pub verifier_snapshot: Option<String>,
+    /// facts generated by each rule of the blocks and the verifier, computed
+    /// again from the final world
+    pub rule_derivations: Vec<RuleDerivation>,
+    pub query_result: Vec<Fact>,
+    /// why `query` could not run, like a `RunLimit` error when generating the
+    /// world takes longer than `verifier_limits` allow
+    pub query_error: Option<String>,
+    /// parse errors of `query`, with positions in the query text
+    pub query_editor: Option<Editor>,
+    pub query_results: Vec<NamedQueryResult>,
+    /// one per `scenarios`, in the same order
+    pub scenario_results: Vec<ScenarioResult>,
+    pub summary: ResultSummary,
+    /// names of the predicates defined by a fact or rule in the token blocks,
+    /// the verifier or the ambient facts
+    pub defined_predicates: Vec<String>,
+    /// failure of the pipeline that is not tied to an editor, like a token that
+    /// could not be built, serialized or verified
+    pub error: Option<String>,
+}
 
-        let mut token = builder.build_with_rng(&mut rng).unwrap();
+/// counts for a status line, taken from the detailed fields of the result
+#[derive(Default, Serialize, Deserialize, Tsify)]
+struct ResultSummary {
+    /// number of blocks in the token, authority included
+    pub block_count: usize,
+    /// length of `checks`
+    pub total_checks: usize,
+    /// checks of `checks` that did not pass
+    pub failed_checks: usize,
+    /// length of `policies`, disabled ones included
+    pub policy_count: usize,
+    /// `world_total_facts`
+    pub world_fact_count: usize,
+    /// true if the verifier ran and an allow policy matched
+    pub verification_passed: bool,
+}
+
+impl BiscuitResult {
+    fn with_summary(mut self) -> Self {
+        self.summary = ResultSummary {
+            block_count: self.block_count,
+            total_checks: self.checks.len(),
+            failed_checks: self.checks.iter().filter(|check| !check.passed).count(),
+            policy_count: self.policies.len(),
+            world_fact_count: self.world_total_facts,
+            verification_passed: matches!(
+                self.verifier_result.as_ref().map(|result| &result.status),
+                Some(VerifierStatus::Success { .. })
+            ),
+        };
+        self
+    }
+}
 
-        for (i, code) in (&query.token_blocks[1..]).iter().enumerate() {
-            let mut editor = Editor::default();
-            let mut block = Block::default();
+#[derive(Serialize, Deserialize, Tsify)]
+struct ScenarioResult {
+    pub name: String,
+    /// true if an allow policy matched
+    pub passed: bool,
+    pub verifier_result: Option<VerifierResult>,
+    pub matched_policy: Option<MatchedPolicy>,
+    /// the checks of the token and of the scenario that failed
+    pub failed_checks: Vec<String>,
+    /// errors and markers of the scenario's verifier code
+    pub verifier_editor: Option<Editor>,
+    pub error: Option<String>,
+}
 
-            let temp_keypair = KeyPair::new_with_rng(&mut rng);
-            let mut builder = token.create_block();
+#[derive(Serialize, Deserialize, Tsify)]
+struct VerifierResult {
+    pub status: VerifierStatus,
+    /// human readable version of the status
+    pub message: String,
+}
 
-            match parse_source(&code) {
-                Err(errors) => {
-                    error!("error: {:?}", errors);
-                    editor.errors = get_parse_errors(&code, errors);
-                },
-                Ok((_, block_parsed)) => {
-                    for (_, fact) in block_parsed.facts.iter() {
-                        builder.add_fact(fact.clone()).unwrap();
-                    }
+#[derive(Serialize, Deserialize, Tsify)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum VerifierStatus {
+    /// `policy` is the index in the verifier code, as in `policies`
+    Success { policy: usize },
+    FailedChecks { checks: Vec<String> },
+    Deny { policy: usize },
+    NoMatchingPolicy,
+    Timeout,
+    Other { error: String },
+}
 
-                    for (_, rule) in block_parsed.rules.iter() {
-                        builder.add_rule(rule.clone()).unwrap();
-                    }
+impl VerifierResult {
+    fn new(result: &Result<usize, error::Token>, policies: &[VerifierPolicy]) -> Self {
+        // the verifier counts the policies it was given, without the disabled ones
+        let policy = |index: &usize| policies.get(*index).map(|policy| policy.index).unwrap_or(*index);
 
-                    for (i, check) in block_parsed.checks.iter() {
-                        builder.add_check(check.clone()).unwrap();
-                        let position = get_position(&code, i);
-                        block.checks.push((position, true));
-                    }
+        let status = match result {
+            Ok(index) => VerifierStatus::Success { policy: policy(index) },
+            Err(error::Token::FailedLogic(error::Logic::FailedChecks(v))) => {
+                VerifierStatus::FailedChecks {
+                    checks: v.iter().map(|e| match e {
+                        error::FailedCheck::Verifier(c) => c.rule.clone(),
+                        error::FailedCheck::Block(c) => c.rule.clone(),
+                    }).collect(),
                 }
-            }
+            },
+            Err(error::Token::FailedLogic(error::Logic::Deny(index))) => {
+                VerifierStatus::Deny { policy: policy(index) }
+            },
+            Err(error::Token::FailedLogic(error::Logic::NoMatchingPolicy)) => {
+                VerifierStatus::NoMatchingPolicy
+            },
+            Err(error::Token::RunLimit(error::RunLimit::Timeout)) => VerifierStatus::Timeout,
+            Err(e) => VerifierStatus::Other { error: format!("{:?}", e) },
+        };
 
-            token = token
-                .append_with_rng(&mut rng, &temp_keypair, builder)
-                .unwrap();
+        let message = match result {
+            Err(error::Token::RunLimit(error::RunLimit::Timeout)) => {
+                "Error: verification timed out".to_string()
+            },
+            Err(e) => format!("Error: {:?}", e),
+            Ok(_) => "Success".to_string(),
+        };
 
-            blocks.push(block);
-            biscuit_result.token_blocks.push(editor);
-        }
+        VerifierResult { status, message }
+    }
+}
 
-        let v = token.to_vec().unwrap();
-        //self.serialized = Some(base64::encode_config(&v[..], base64::URL_SAFE));
-        //self.biscuit = Some(token);
-        biscuit_result.token_content = token.print();
+#[derive(Serialize, Deserialize, Tsify)]
+struct VerifierError {
+    /// one of "failed_check", "deny", "no_matching_policy", "timeout",
+    /// "too_many_facts", "too_many_iterations" or "other"
+    pub kind: String,
+    pub check_id: Option<u32>,
+    /// set for failed block checks, 0 is the authority block
+    pub block_id: Option<u32>,
+    /// index in `token_blocks` of the block with the failed check. It differs
+    /// from `block_id` when disabled or empty blocks were left out of the token
+    pub editor_id: Option<u32>,
+    /// the check or policy that failed
+    pub rule: Option<String>,
+    /// for failed checks, the expressions that rejected every set of values
+    /// matching the check's predicates
+    pub failing_expressions: Vec<String>,
+    /// for failed checks, the values of the variables matching the check's
+    /// predicates, before the expressions are applied. At most `MAX_BINDINGS`
+    pub bindings: Vec<HashMap<String, Term>>,
+    /// for failed checks, why `failing_expressions` and `bindings` could not
+    /// be computed, like the limits being reached while querying the world
+    pub explanation_error: Option<String>,
+}
 
-        token_opt = Some(token);
+impl VerifierError {
+    fn new(kind: &str) -> Self {
+        VerifierError {
+            kind: kind.to_string(),
+            check_id: None,
+            block_id: None,
+            rule: None,
+            failing_expressions: Vec::new(),
+            bindings: Vec::new(),
+            explanation_error: None,
+            editor_id: None,
+        }
     }
+}
 
-    if let Some(verifier_code) = query.verifier_code.as_ref() {
-        let mut verifier = match token_opt {
-            Some(token) => token.verify(root.public()).unwrap(),
-            None => Verifier::new().unwrap(),
-        };
+const MAX_BINDINGS: usize = 20;
 
-        biscuit_result.verifier_editor = Some(Editor::default());
-        //info!("verifier source:\n{}", &verifier_code);
+// the verifier does not tell which part of a check failed, so each query of
+// the check is run again against the world: first with its predicates only
+// to get the candidate bindings, then with each expression separately. The
+// explanation is left empty if one of the queries fails
+fn explain_failed_check(verifier: &mut Verifier, error: &mut VerifierError, limits: &VerifierLimits) {
+    let check = match error.rule.as_deref().map(builder::Check::try_from) {
+        Some(Ok(check)) => check,
+        _ => return,
+    };
 
-        let verifier_result;
+    for builder::Rule(_, body, expressions) in check.queries.iter() {
+        // the engine never returns from a query without predicates, and
+        // there are no bindings to report for those anyway
+        if body.is_empty() {
+            continue;
+        }
 
-        let res = parse_source(&verifier_code);
-        if let Err(errors) = res {
-            biscuit_result.verifier_result = Some(format!("errors: {:?}", errors));
-            error!("error: {:?}", errors);
-            if let Some(ed) = biscuit_result.verifier_editor.as_mut() {
-                ed.errors = get_parse_errors(&verifier_code, errors);
+        let mut variables: Vec<builder::Term> = Vec::new();
+        for term in body.iter().flat_map(|predicate| predicate.ids.iter()) {
+            if matches!(term, builder::Term::Variable(_)) && !variables.contains(term) {
+                variables.push(term.clone());
             }
-        } else {
-            let mut verifier_checks = Vec::new();
-            let mut verifier_policies = Vec::new();
-
-            let (_, parsed) = res.unwrap();
+        }
 
-            for (_, fact) in parsed.facts.iter() {
-                verifier.add_fact(fact.clone()).unwrap();
-            }
+        let head = builder::Predicate { name: "bindings".to_string(), ids: variables.clone() };
+        let run = |verifier: &mut Verifier, expressions: Vec<builder::Expression>| -> Result<Vec<builder::Fact>, error::Token> {
+            let rule = builder::Rule(head.clone(), body.clone(), expressions);
+            verifier.query_with_limits(rule, limits.clone())
+        };
+        let fail = |error: &mut VerifierError, e: error::Token| {
+            error.failing_expressions.clear();
+            error.bindings.clear();
+            error.explanation_error = Some(format!("could not explain the failure: {:?}", e));
+        };
 
-            for (_, rule) in parsed.rules.iter() {
-                verifier.add_rule(rule.clone()).unwrap();
-            }
+        let mut candidates = match run(verifier, Vec::new()) {
+            Ok(candidates) => candidates,
+            Err(e) => return fail(error, e),
+        };
+        candidates.sort_by_cached_key(|fact| fact.to_string());
+        if candidates.is_empty() {
+            continue;
+        }
 
-            for (i, check) in parsed.checks.iter() {
-                verifier.add_check(check.clone()).unwrap();
-                let position = get_position(&verifier_code, i);
-                // checks are marked as success until they fail
-                verifier_checks.push((position, true));
+        for expression in expressions.iter() {
+            match run(verifier, vec![expression.clone()]) {
+                Ok(facts) if facts.is_empty() => error.failing_expressions.push(expression_code(expression)),
+                Ok(_) => {},
+                Err(e) => return fail(error, e),
             }
+        }
 
-            for (i, policy) in parsed.policies.iter() {
-                verifier.add_policy(policy.clone()).unwrap();
-                let position = get_position(&verifier_code, i);
-                // checks are marked as success until they fail
-                verifier_policies.push(position);
+        for fact in candidates.into_iter() {
+            if error.bindings.len() >= MAX_BINDINGS {
+                break;
             }
 
-            let mut limits = VerifierLimits::default();
-            limits.max_time = std::time::Duration::from_secs(2);
-            verifier_result = verifier.verify_with_limits(limits);
-
-            let (mut facts, _, _) = verifier.dump();
-            biscuit_result.verifier_world = facts.drain(..).map(|mut fact| {
-                Fact {
-                    name: fact.0.name,
-                    terms: fact.0.ids.drain(..).map(|id| id.to_string()).collect(),
+            let values = variables.iter().zip(fact.0.ids).filter_map(|(variable, value)| {
+                match variable {
+                    builder::Term::Variable(name) => Some((name.clone(), Term::from(value))),
+                    _ => None,
                 }
             }).collect();
+            error.bindings.push(values);
+        }
+    }
+}
 
-            match &verifier_result {
-                Err(error::Token::FailedLogic(error::Logic::FailedChecks(v))) => {
-                    for e in v.iter() {
-                        match e {
-                            error::FailedCheck::Verifier(error::FailedVerifierCheck {
-                                check_id, ..
-                            }) => {
-
-                                verifier_checks[*check_id as usize].1 = false;
-                            }
-                            error::FailedCheck::Block(error::FailedBlockCheck {
-                                block_id,
-                                check_id,
-                                ..
-                            }) => {
-                                let block = if *block_id == 0 {
-                                    &mut authority
-                                } else {
-                                    &mut blocks[*block_id as usize - 1]
-                                };
-                                block.checks[*check_id as usize].1 = false;
-                            }
-                        }
+fn get_verifier_errors(
+    result: &Result<usize, error::Token>,
+    policies: &[VerifierPolicy],
+) -> Vec<VerifierError> {
+    match result {
+        Ok(_) => Vec::new(),
+        Err(error::Token::FailedLogic(error::Logic::FailedChecks(v))) => {
+            v.iter().map(|e| match e {
+                error::FailedCheck::Verifier(error::FailedVerifierCheck { check_id, rule }) => {
+                    VerifierError {
+                        check_id: Some(*check_id),
+                        rule: Some(rule.clone()),
+                        ..VerifierError::new("failed_check")
                     }
                 },
-                Err(error::Token::FailedLogic(error::Logic::Deny(index))) => {
-                    let position = &verifier_policies[*index];
-                    if let Some(ed) = biscuit_result.verifier_editor.as_mut() {
-                        ed.markers.push(Marker { ok: false, position: position.clone() });
-                    }
-                },
-                Ok(index) => {
-                    let position = &verifier_policies[*index];
-                    if let Some(ed) = biscuit_result.verifier_editor.as_mut() {
-                        ed.markers.push(Marker { ok: true, position: position.clone() });
+                error::FailedCheck::Block(error::FailedBlockCheck { block_id, check_id, rule }) => {
+                    VerifierError {
+                        check_id: Some(*check_id),
+                        block_id: Some(*block_id),
+                        rule: Some(rule.clone()),
+                        ..VerifierError::new("failed_check")
                     }
                 },
-                _ => {},
-            }
-
-            for (position, result) in authority.checks.iter() {
-                if let Some(ed) = biscuit_result.token_blocks.get_mut(0) {
-                    ed.markers.push(Marker { ok: *result, position: position.clone() });
-                }
-            }
+            }).collect()
+        },
+        Err(error::Token::FailedLogic(error::Logic::Deny(index))) => {
+            vec![VerifierError {
+                rule: policies.get(*index).map(|p| p.policy.to_string()),
+                ..VerifierError::new("deny")
+            }]
+        },
+        Err(error::Token::FailedLogic(error::Logic::NoMatchingPolicy)) => {
+            vec![VerifierError::new("no_matching_policy")]
+        },
+        Err(error::Token::RunLimit(error::RunLimit::Timeout)) => vec![VerifierError::new("timeout")],
+        Err(error::Token::RunLimit(error::RunLimit::TooManyFacts)) => {
+            vec![VerifierError::new("too_many_facts")]
+        },
+        Err(error::Token::RunLimit(error::RunLimit::TooManyIterations)) => {
+            vec![VerifierError::new("too_many_iterations")]
+        },
+        Err(_) => vec![VerifierError::new("other")],
+    }
+}
 
-            for (id, block) in blocks.iter().enumerate() {
-                for (position, result) in block.checks.iter() {
-                    if let Some(ed) = biscuit_result.token_blocks.get_mut(id+1) {
-                        ed.markers.push(Marker { ok: *result, position: position.clone() });
-                    }
-                }
-            }
+#[derive(Default, Serialize, Deserialize, Tsify)]
+struct NamedQueryResult {
+    pub name: String,
+    pub facts: Vec<Fact>,
+    pub error: Option<String>,
+}
 
-            for (position, result) in verifier_checks.iter() {
-                if let Some(ed) = biscuit_result.verifier_editor.as_mut() {
-                    ed.markers.push(Marker { ok: *result, position: position.clone() });
-                }
-            }
+#[derive(Serialize, Deserialize, Tsify)]
+struct RuleDerivation {
+    pub rule: String,
+    pub position: SourcePosition,
+    /// editor containing the rule
+    pub source: MarkerSource,
+    pub facts: Vec<Fact>,
+    /// set if the rule could not be run again against the world
+    pub error: Option<String>,
+}
 
-            biscuit_result.verifier_result = Some(match &verifier_result {
-                Err(e) => format!("Error: {:?}", e),
-                Ok(_) => "Success".to_string(),
-            });
+#[derive(Serialize, Deserialize, Tsify)]
+struct MatchedPolicy {
+    pub index: usize,
+    pub text: String,
+    pub position: SourcePosition,
+    /// "allow" or "deny"
+    pub kind: String,
+}
 
-            if let Some(query) = query.query.as_ref() {
-                log(&format!("got query content: {}", query));
-
-                if !query.is_empty() {
-                    let query_result: Result<Vec<builder::Fact>, biscuit_auth::error::Token> =
-                        verifier.query(query.as_str());
-                    match query_result {
-                        Err(e) => {
-                            log(&format!("query error: {:?}", e));
-                        },
-                        Ok(mut facts) => {
-                            biscuit_result.query_result = facts.drain(..).map(|mut fact| {
-                                Fact {
-                                    name: fact.0.name,
-                                    terms: fact.0.ids.drain(..).map(|id| id.to_string()).collect(),
-                                }
-                            }).collect();
-                        }
-                    }
-                }
-            }
+impl MatchedPolicy {
+    fn new(policy: &VerifierPolicy) -> Self {
+        MatchedPolicy {
+            index: policy.index,
+            text: policy.source.clone(),
+            position: policy.position.clone(),
+            kind: get_policy_kind(&policy.policy).to_string(),
         }
+    }
+}
+
+#[derive(Serialize, Deserialize, Tsify)]
+struct CheckResult {
+    pub source: MarkerSource,
+    /// index of the check in its block, as `check_id` in `verifier_errors`
+    pub index: usize,
+    pub text: String,
+    pub position: SourcePosition,
+    pub passed: bool,
+}
+
+#[derive(Serialize, Deserialize, Tsify)]
+struct PolicyEvaluation {
+    pub index: usize,
+    pub text: String,
+    pub position: SourcePosition,
+    /// "allow" or "deny"
+    pub kind: String,
+    /// "matched", "not_matched", or "not_reached" for the policies after the
+    /// matched one and for all of them if the checks failed or the verifier
+    /// hit a limit. "disabled" for the policies of `disabled_policies`
+    pub status: String,
+}
+
+// policies are tried in order until one matches
+fn get_policy_evaluations(
+    result: &Result<usize, error::Token>,
+    policies: &[VerifierPolicy],
+    disabled: &[VerifierPolicy],
+) -> Vec<PolicyEvaluation> {
+    let (reached, matched) = match result {
+        Ok(index) | Err(error::Token::FailedLogic(error::Logic::Deny(index))) => (true, Some(*index)),
+        Err(error::Token::FailedLogic(error::Logic::NoMatchingPolicy)) => (true, None),
+        Err(_) => (false, None),
+    };
+
+    let evaluation = |policy: &VerifierPolicy, status: &str| PolicyEvaluation {
+        index: policy.index,
+        text: policy.source.clone(),
+        position: policy.position.clone(),
+        kind: get_policy_kind(&policy.policy).to_string(),
+        status: status.to_string(),
+    };
+
+    let mut evaluations: Vec<PolicyEvaluation> = policies.iter().enumerate().map(|(index, policy)| {
+        let status = match matched {
+            _ if !reached => "not_reached",
+            Some(matched) if index == matched => "matched",
+            Some(matched) if index > matched => "not_reached",
+            _ => "not_matched",
+        };
+        evaluation(policy, status)
+    }).collect();
 
+    evaluations.extend(disabled.iter().map(|policy| evaluation(policy, "disabled")));
+    evaluations.sort_by_key(|policy| policy.index);
+    evaluations
+}
+
+fn get_policy_kind(policy: &builder::Policy) -> &'static str {
+    match policy.kind {
+        builder::PolicyKind::Allow => "allow",
+        builder::PolicyKind::Deny => "deny",
     }
+}
 
-    biscuit_result
+#[derive(Clone, Default, Serialize, Deserialize, Tsify)]
+struct Editor {
+    pub errors: Vec<ParseError>,
+    /// possible mistakes that do not prevent generating the token, like
+    /// predicates that no fact or rule defines or a stray `;`
+    pub warnings: Vec<ParseError>,
+    pub markers: Vec<Marker>,
+    /// position of every fact, rule, check and policy that was loaded
+    pub elements: Vec<Element>,
 }
 
-#[wasm_bindgen(start)]
-pub fn run_app() {
-    wasm_logger::init(wasm_logger::Config::default());
-    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+struct Element {
+    /// "fact", "rule", "check" or "policy"
+    pub kind: String,
+    pub position: SourcePosition,
+}
 
-    unsafe { log("wasm run_app") }
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+struct Marker {
+    /// "check", "policy", "unused_rule" for rules that generated no facts, or
+    /// "policy_not_reached" and "policy_disabled" for policies that were not
+    /// evaluated, which are always `ok`
+    pub kind: String,
+    pub ok: bool,
+    pub position: SourcePosition,
+    /// editor the marker belongs to
+    pub source: MarkerSource,
+    /// for failed checks, which check failed and its text
+    pub message: Option<String>,
 }
 
-// based on nom's convert_error
-fn get_position(input: &str, span: &str) -> SourcePosition {
-    let offset = input.offset(span);
-    let prefix = &input.as_bytes()[..offset];
+impl Marker {
+    fn new(kind: &str, ok: bool, position: &SourcePosition, source: MarkerSource) -> Self {
+        Marker { kind: kind.to_string(), ok, position: position.clone(), source, message: None }
+    }
 
-    // Count the number of newlines in the first `offset` bytes of input
-    let line_start = prefix.iter().filter(|&&b| b == b'\n').count();
+    fn check(failure: &Option<String>, position: &SourcePosition, source: MarkerSource) -> Self {
+        Marker { message: failure.clone(), ..Marker::new("check", failure.is_none(), position, source) }
+    }
+}
 
-    // Find the line that includes the subslice:
-    // find the *last* newline before the substring starts
-    let line_begin = prefix
-        .iter()
-        .rev()
-        .position(|&b| b == b'\n')
-        .map(|pos| offset - pos)
-        .unwrap_or(0);
+/// serialized as `{ "type": "authority" }`, `{ "type": "block", "value": index }`
+/// with the index in `token_blocks`, or `{ "type": "verifier" }`
+#[derive(Clone, Copy, Serialize, Deserialize, Tsify)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+enum MarkerSource {
+    Authority,
+    Block(u32),
+    Verifier,
+}
 
-    // Find the full line after that newline
-    let line = input[line_begin..]
-        .lines()
-        .next()
-        .unwrap_or(&input[line_begin..])
-        .trim_end();
+impl MarkerSource {
+    fn block(index: usize) -> Self {
+        if index == 0 {
+            MarkerSource::Authority
+        } else {
+            MarkerSource::Block(index as u32)
+        }
+    }
+}
 
-    // The (1-indexed) column number is the offset of our substring into that line
-    let column_start = line.offset(span);
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+struct ParseError {
+    pub message: String,
+    pub position: SourcePosition,
+    /// kind of element the error occurred in: "fact", "rule", "check" or "policy"
+    pub component: String,
+    pub severity: Severity,
+    pub code: ErrorCode,
+    /// the text at `position`, up to the end of its line and at most
+    /// `MAX_SNIPPET_LENGTH` characters. Empty at the end of the input
+    pub snippet: String,
+}
 
-    let offset = offset + span.len();
-    let prefix = &input.as_bytes()[..offset];
+const MAX_SNIPPET_LENGTH: usize = 40;
 
-    // Count the number of newlines in the first `offset` bytes of input
-    let line_end = prefix.iter().filter(|&&b| b == b'\n').count();
+fn get_snippet(span: &str) -> String {
+    span.lines().next().unwrap_or("").chars().take(MAX_SNIPPET_LENGTH).collect()
+}
 
-    // Find the line that includes the subslice:
-    // find the *last* newline before the substring starts
-    let line_begin = prefix
-        .iter()
-        .rev()
-        .position(|&b| b == b'\n')
-        .map(|pos| offset - pos)
-        .unwrap_or(0);
+/// stable identifier of the error, while `message` may change
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "snake_case")]
+enum ErrorCode {
+    /// the parser did not expect this text here
+    UnexpectedToken,
+    /// the statement stops before it is complete
+    UnexpectedEnd,
+    /// a string without its closing `"`
+    UnclosedString,
+    UnexpectedParenthesis,
+    VariableInFact,
+    /// a variable of the rule head is not used in its body
+    UnboundHeadVariable,
+    /// `reject if`, from later versions of biscuit
+    UnsupportedReject,
+    /// a statement that parsed but was refused by the token or verifier
+    InvalidElement,
+    /// warning for a predicate that no fact or rule defines
+    UndefinedPredicate,
+    /// a query that only has expressions, which the engine cannot run
+    QueryWithoutPredicates,
+    /// a rule that only has expressions, which would never let the verifier return
+    RuleWithoutPredicates,
+    /// warning for a `;` that ends no statement
+    EmptyStatement,
+    /// warning for a `/*` comment that runs until the end of the input, or
+    /// error when it is in one of several verifier snippets
+    UnclosedComment,
+}
 
-    // Find the full line after that newline
-    let line = input[line_begin..]
-        .lines()
-        .next()
-        .unwrap_or(&input[line_begin..])
-        .trim_end();
+impl ErrorCode {
+    fn new(input: &str, e: &biscuit_auth::parser::Error) -> Self {
+        let statement = get_statement(input, e.input);
+        let message = e.message.as_deref().unwrap_or("");
+
+        if let Some(recoverable) = Recoverable::new(input, e) {
+            recoverable.code()
+        } else if statement.starts_with("reject") {
+            ErrorCode::UnsupportedReject
+        } else if message.starts_with("variables are not allowed in facts") {
+            ErrorCode::VariableInFact
+        } else if message.starts_with("rule head contains variables") {
+            ErrorCode::UnboundHeadVariable
+        } else if message.starts_with("unexpected parens") {
+            ErrorCode::UnexpectedParenthesis
+        } else if (statement.matches('"').count() - statement.matches("\\\"").count()) % 2 == 1 {
+            ErrorCode::UnclosedString
+        } else if e.input.trim().is_empty() {
+            ErrorCode::UnexpectedEnd
+        } else {
+            ErrorCode::UnexpectedToken
+        }
+    }
+}
 
-    // The (1-indexed) column number is the offset of our substring into that line
-    let column_end = line.offset(&span[span.len()..]) + 1;
+/// warnings are the mistakes that still produce a token: syntax errors the
+/// parser can recover from, and possible mistakes found by the linter
+#[derive(Clone, Copy, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "snake_case")]
+enum Severity {
+    Error,
+    Warning,
+}
+
+/// location of an element in the source code. Lines and columns both start
+/// at 0, columns are byte offsets into their line, and the end is exclusive.
+/// `one_based` is the same location for editors that count from 1
+#[derive(Clone, Debug, Serialize, Deserialize, Tsify)]
+struct SourcePosition {
+    pub line_start: usize,
+    pub column_start: usize,
+    pub line_end: usize,
+    pub column_end: usize,
+    pub one_based: OneBasedPosition,
+}
 
-    SourcePosition {
-        line_start,
-        column_start,
-        line_end,
-        column_end,
+impl SourcePosition {
+    fn new(line_start: usize, column_start: usize, line_end: usize, column_end: usize) -> Self {
+        SourcePosition {
+            line_start,
+            column_start,
+            line_end,
+            column_end,
+            one_based: OneBasedPosition {
+                line_start: line_start + 1,
+                column_start: column_start + 1,
+                line_end: line_end + 1,
+                column_end: column_end + 1,
+            },
+        }
     }
 }
 
-#[derive(Clone, Debug)]
-struct Block {
+/// lines and columns counted from 1, as in Monaco. The end is still exclusive
+#[derive(Clone, Debug, Serialize, Deserialize, Tsify)]
+struct OneBasedPosition {
+    pub line_start: usize,
+    pub column_start: usize,
+    pub line_end: usize,
+    pub column_end: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Tsify)]
+struct Fact {
+    pub name: String,
+    pub terms: Vec<Term>,
+    /// each term in datalog syntax, as it can be pasted in an editor
+    pub term_codes: Vec<String>,
+    /// the whole fact in datalog syntax, without the final `;`
     pub code: String,
-    pub checks: Vec<(SourcePosition, bool)>,
-    pub enabled: bool,
+    /// index of the block declaring this fact (0 is the authority block).
+    ///
+    /// The verifier does not keep track of where facts come from, so this
+    /// is found by comparing with the facts written in each block: facts
+    /// provided by the verifier or generated by rules have no origin, and a
+    /// fact declared in multiple blocks is attributed to the first one
+    pub origin: Option<u32>,
 }
 
-impl Default for Block {
-    fn default() -> Self {
-        Block {
-            code: String::new(),
-            checks: Vec::new(),
-            enabled: true,
+impl From<builder::Fact> for Fact {
+    fn from(mut fact: builder::Fact) -> Self {
+        Fact {
+            term_codes: fact.0.ids.iter().map(term_code).collect(),
+            code: predicate_code(&fact.0),
+            name: fact.0.name,
+            terms: fact.0.ids.drain(..).map(Term::from).collect(),
+            origin: None,
         }
     }
 }
 
-fn get_parse_errors(input: &str, errors: Vec<biscuit_auth::parser::Error>) -> Vec<ParseError> {
-    let mut res = Vec::new();
-
-    error!("got errors: {:?}", errors);
-    for e in errors.iter() {
-        let position = get_position(input, e.input);
-        let message = e.message.as_ref().cloned().unwrap_or_else(|| format!("error: {:?}", e.code));
+#[derive(Clone, Debug, Serialize, Deserialize, Tsify)]
+struct Predicate {
+    pub name: String,
+    pub terms: Vec<Term>,
+}
 
-        error!("position for error({:?}) \"{}\": {:?}", e.code, message, position);
-        res.push(ParseError { message, position });
+impl From<builder::Predicate> for Predicate {
+    fn from(predicate: builder::Predicate) -> Self {
+        Predicate {
+            name: predicate.name,
+            terms: predicate.ids.into_iter().map(Term::from).collect(),
+        }
     }
+}
 
-    res
+/// structured content of a token, the counterpart of `token_content`
+#[derive(Serialize, Deserialize, Tsify)]
+struct TokenJson {
+    pub authority: BlockJson,
+    pub blocks: Vec<BlockJson>,
+}
+
+#[derive(Serialize, Deserialize, Tsify)]
+struct BlockJson {
+    pub index: u32,
+    pub context: Option<String>,
+    pub facts: Vec<Fact>,
+    pub rules: Vec<RuleJson>,
+    pub checks: Vec<CheckJson>,
+}
+
+#[derive(Serialize, Deserialize, Tsify)]
+struct RuleJson {
+    /// the rule as datalog
+    pub source: String,
+    pub head: Predicate,
+    pub body: Vec<Predicate>,
+    pub expressions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Tsify)]
+struct CheckJson {
+    /// the check as datalog
+    pub source: String,
+    /// the check succeeds if any of them matches
+    pub queries: Vec<RuleJson>,
+}
+
+/// serialized as `{ "type": ..., "value": ... }`, with integers and booleans
+/// kept as JSON numbers and booleans. Integers that a JS number cannot hold
+/// exactly are strings
+#[derive(Clone, Debug, Serialize, Deserialize, Tsify)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+enum Term {
+    String(String),
+    Int(
+        #[serde(serialize_with = "serialize_int", deserialize_with = "deserialize_int")]
+        #[tsify(type = "number | string")]
+        i64,
+    ),
+    Bool(bool),
+    /// RFC 3339 format
+    Date(String),
+    /// hex encoded with a `hex:` prefix
+    Bytes(String),
+    /// written without the `#` prefix
+    Symbol(String),
+    /// written without the `$` prefix
+    Variable(String),
+    /// the elements of the set, sorted
+    Set(Vec<Term>),
+}
+
+/// largest integer a JS number holds exactly, `Number.MAX_SAFE_INTEGER`
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+// the JSON compatible serializer refuses the integers beyond ±2^53, which
+// would turn the whole result into an error
+fn serialize_int<S: serde::Serializer>(value: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+    if value.unsigned_abs() <= MAX_SAFE_INTEGER {
+        serializer.serialize_i64(*value)
+    } else {
+        serializer.serialize_str(&value.to_string())
+    }
+}
+
+fn deserialize_int<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Int {
+        Number(i64),
+        String(String),
+    }
+
+    match Int::deserialize(deserializer)? {
+        Int::Number(value) => Ok(value),
+        Int::String(value) => value.trim().parse()
+            .map_err(|e| serde::de::Error::custom(format!("invalid integer \"{}\": {}", value, e))),
+    }
+}
+
+impl From<builder::Term> for Term {
+    fn from(term: builder::Term) -> Self {
+        match term {
+            builder::Term::Str(s) => Term::String(s),
+            builder::Term::Integer(i) => Term::Int(i),
+            builder::Term::Bool(b) => Term::Bool(b),
+            builder::Term::Date(d) => {
+                let value = chrono::DateTime::from_timestamp(d as i64, 0)
+                    .map(|date| date.to_rfc3339())
+                    .unwrap_or_else(|| d.to_string());
+                Term::Date(value)
+            },
+            builder::Term::Bytes(bytes) => Term::Bytes(format!("hex:{}", hex::encode(bytes))),
+            builder::Term::Symbol(s) => Term::Symbol(s),
+            builder::Term::Variable(v) => Term::Variable(v),
+            builder::Term::Set(set) => Term::Set(set.into_iter().map(Term::from).collect()),
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub fn execute(query: &JsBiscuitQuery) -> JsBiscuitResult {
+    let result = match from_js(query) {
+        Ok(query) => execute_inner(query),
+        Err(e) => BiscuitResult { error: Some(e), ..Default::default() },
+    };
+
+    to_js(&result.with_summary())
+}
+
+fn execute_inner(mut query: BiscuitQuery) -> BiscuitResult {
+    let mut biscuit_result = BiscuitResult::default();
+
+    // checked before parsing anything, as the verifier limits only apply
+    // once the datalog is loaded
+    let input_size = query.input_size();
+    let max_input_size = query.max_input_size.unwrap_or(DEFAULT_MAX_INPUT_SIZE);
+    if input_size > max_input_size {
+        biscuit_result.error = Some(format!(
+            "input too large: {} bytes of datalog, the limit is {} bytes",
+            input_size, max_input_size,
+        ));
+        return biscuit_result;
+    }
+
+    let mut verifier_start_lines = None;
+    if let Some(blocks) = query.verifier_blocks.as_ref() {
+        let (code, start_lines) = join_verifier_blocks(blocks);
+        query.verifier_code = Some(code);
+        verifier_start_lines = Some(start_lines);
+    }
+
+    info!("will generate token");
+
+    // only a throwaway root key can be signed with seeded randomness
+    let mut rng: StdRng = match (query.root_private_key.as_ref(), query.root_seed) {
+        (None, Some(seed)) => SeedableRng::seed_from_u64(seed),
+        _ => SeedableRng::from_entropy(),
+    };
+    let root = match query.root_private_key.as_ref() {
+        Some(key) => match parse_private_key(key) {
+            Some(key) => KeyPair::from(key),
+            None => {
+                biscuit_result.error = Some("invalid root private key".to_string());
+                return biscuit_result;
+            },
+        },
+        None => KeyPair::new_with_rng(&mut rng),
+    };
+
+    let mut builder = Biscuit::builder(&root);
+
+    // check positions for each block, authority first
+    let mut blocks = Vec::new();
+
+    let mut token_opt = None;
+
+    if !query.token_blocks.is_empty() {
+        let mut authority = Block::default();
+        let mut authority_editor = Editor::default();
+
+        // the token needs an authority block, so a disabled one is left empty
+        let code = query.token_blocks[0].as_str();
+        let code = if query.block_enabled(0) && !is_empty_block(code) { code } else { "" };
+
+        match parse_authority(code) {
+            Err(errors) => {
+                error!("error: {:?}", errors.iter().map(|e| e.message.as_str()).collect::<Vec<_>>());
+                authority_editor.errors = errors;
+                if query.fail_fast.unwrap_or(false) {
+                    biscuit_result.token_blocks.push(authority_editor);
+                    biscuit_result.error = Some(parse_failure(0));
+                    return biscuit_result;
+                }
+            },
+            Ok(authority_parsed) => {
+                for (span, fact) in authority_parsed.facts.iter() {
+                    let i = &code[span.clone()];
+                    match builder.add_authority_fact(fact.clone()) {
+                        Err(e) => authority_editor.errors.push(get_token_error(code, i, e)),
+                        Ok(()) => authority.facts.push(get_position(code, i)),
+                    }
+                }
+
+                for (span, rule) in authority_parsed.rules.iter() {
+                    let i = &code[span.clone()];
+                    if rule.1.is_empty() {
+                        authority_editor.errors.push(get_rule_error(code, i));
+                        continue;
+                    }
+                    match builder.add_authority_rule(rule.clone()) {
+                        Err(e) => authority_editor.errors.push(get_token_error(code, i, e)),
+                        Ok(()) => authority.rules.push((get_position(code, i), rule.clone())),
+                    }
+                }
+
+                for (span, check) in authority_parsed.checks.iter() {
+                    let i = &code[span.clone()];
+                    // only checks that made it into the token can be reported as failed
+                    match builder.add_authority_check(check.clone()) {
+                        Err(e) => authority_editor.errors.push(get_token_error(code, i, e)),
+                        Ok(()) => {
+                            let position = get_position(code, i);
+                            authority.checks.push((position, check.clone(), None));
+                        }
+                    }
+                }
+            }
+        }
+
+        authority_editor.elements = authority.elements();
+        blocks.push(authority);
+        biscuit_result.token_blocks.push(authority_editor);
+
+        let mut token = match builder.build_with_rng(&mut rng) {
+            Ok(token) => token,
+            Err(e) => {
+                error!("could not build token: {:?}", e);
+                biscuit_result.error = Some(format!("could not build token: {:?}", e));
+                return biscuit_result;
+            }
+        };
+
+        let codes: Vec<Option<&str>> = query.token_blocks.iter()
+            .enumerate()
+            .skip(1)
+            .map(|(index, code)| Some(code.as_str()).filter(|_| query.block_enabled(index)))
+            .collect();
+
+        token = append_blocks(
+            token,
+            &codes,
+            &mut rng,
+            AppendOptions {
+                deterministic: query.root_private_key.is_none() && query.deterministic.unwrap_or(false),
+                block_seed: query.block_seed.filter(|_| query.root_private_key.is_none()),
+                fail_fast: query.fail_fast.unwrap_or(false),
+            },
+            &mut biscuit_result,
+            &mut blocks,
+        );
+        if query.fail_fast.unwrap_or(false) && biscuit_result.error.is_some() {
+            return biscuit_result;
+        }
+
+        set_token_info(&mut biscuit_result, &token, query.print_raw.unwrap_or(false));
+        let serialized = if query.seal.unwrap_or(false) {
+            let secret = query.seal_secret.as_deref().unwrap_or("");
+            token.seal(secret.as_bytes())
+        } else {
+            token.to_vec()
+        };
+
+        // the size and fingerprint describe the returned bytes, which differ
+        // from the ones of `set_token_info` once sealed
+        match serialized {
+            Ok(v) => {
+                biscuit_result.token_size = v.len();
+                biscuit_result.token_fingerprint = Some(hex::encode(Sha256::digest(&v)));
+                biscuit_result.serialized = Some(base64::encode_config(&v[..], base64::URL_SAFE));
+                biscuit_result.serialized_hex = Some(hex::encode(&v));
+                biscuit_result.sealed = query.seal.unwrap_or(false);
+                if query.armored.unwrap_or(false) {
+                    biscuit_result.serialized_armored = biscuit_result.serialized.as_deref().map(armor);
+                }
+            },
+            Err(e) => {
+                error!("could not serialize token: {:?}", e);
+                biscuit_result.error = Some(format!("could not serialize token: {:?}", e));
+                biscuit_result.token_size = 0;
+                biscuit_result.token_fingerprint = None;
+            }
+        }
+        biscuit_result.root_public_key = hex::encode(root.public().to_bytes());
+
+        token_opt = Some(token);
+    }
+
+    // the blocks keep the failed checks of the main verifier, so the
+    // scenarios start from a copy
+    let scenario_blocks = blocks.clone();
+
+    if let Some(verifier_code) = query.verifier_code.as_ref() {
+        let fact_origins = token_opt.as_ref().map(get_fact_origins).unwrap_or_default();
+        if token_opt.is_some() {
+            biscuit_result.verifier_trusted_keys = vec![hex::encode(root.public().to_bytes())];
+        }
+        let verifier = match token_opt.as_ref() {
+            Some(token) => token.verify(root.public()),
+            None => Verifier::new().map_err(error::Token::FailedLogic),
+        };
+        let mut verifier = match verifier {
+            Ok(verifier) => verifier,
+            Err(e) => {
+                error!("could not create verifier: {:?}", e);
+                biscuit_result.error = Some(format!("could not create verifier: {:?}", e));
+                return biscuit_result;
+            }
+        };
+
+        if let Err(e) = add_time_fact(&mut verifier, query.current_time.as_deref()) {
+            biscuit_result.error = Some(e);
+        }
+
+        if let Some(ambient) = query.ambient.as_ref() {
+            if let Err(e) = add_ambient_facts(&mut verifier, ambient) {
+                biscuit_result.error = Some(e);
+            }
+        }
+
+        if let Some(facts) = query.world_facts_json.as_ref() {
+            if let Err(e) = add_json_facts(&mut verifier, facts) {
+                biscuit_result.error = Some(e);
+            }
+        }
+
+        let limits = query.verifier_limits.clone().unwrap_or_default().to_limits();
+        let verifier = run_verifier(
+            &mut biscuit_result,
+            verifier,
+            verifier_code,
+            limits.clone(),
+            &mut blocks,
+            &fact_origins,
+            query.disabled_policies.as_deref().unwrap_or_default(),
+        );
+        // the verifier is only missing if its code could not be parsed
+        if query.fail_fast.unwrap_or(false) && verifier.is_none() {
+            biscuit_result.error = Some("the verifier code could not be parsed".to_string());
+        }
+
+        if let Some(filter) = query.world_filter.as_ref() {
+            biscuit_result.verifier_world.retain(|fact| filter.contains(&fact.name));
+        }
+
+        biscuit_result.world_total_facts = biscuit_result.verifier_world.len();
+        if let Some(max_facts) = query.world_max_facts {
+            biscuit_result.world_truncated = biscuit_result.verifier_world.len() > max_facts;
+            biscuit_result.verifier_world.truncate(max_facts);
+        }
+
+        if let Some(mut verifier) = verifier {
+            let queries = query.queries.as_deref().unwrap_or(&[]);
+            run_queries(
+                &mut biscuit_result,
+                &mut verifier,
+                query.query.as_deref(),
+                &query.query_params,
+                queries,
+                limits,
+            );
+        }
+    }
+
+    let limits = query.verifier_limits.clone().unwrap_or_default().to_limits();
+    biscuit_result.scenario_results = query.scenarios.iter().flatten().map(|scenario| {
+        let mut blocks = scenario_blocks.clone();
+        run_scenario(token_opt.as_ref(), root.public(), scenario, query.current_time.as_deref(), limits.clone(), &mut blocks)
+    }).collect();
+
+    set_warnings(&mut biscuit_result, &query);
+
+    if let Some(start_lines) = verifier_start_lines {
+        if let Some(editor) = biscuit_result.verifier_editor.as_ref() {
+            biscuit_result.verifier_editors = split_editor(editor, &start_lines);
+        }
+        let snippets = query.verifier_blocks.iter().flatten();
+        for (editor, snippet) in biscuit_result.verifier_editors.iter_mut().zip(snippets) {
+            editor.errors.extend(get_unclosed_comment_error(snippet));
+        }
+        biscuit_result.verifier_code = query.verifier_code;
+    }
+
+    biscuit_result
+}
+
+fn run_scenario(
+    token: Option<&Biscuit>,
+    root: PublicKey,
+    scenario: &VerifierScenario,
+    current_time: Option<&str>,
+    limits: VerifierLimits,
+    blocks: &mut [Block],
+) -> ScenarioResult {
+    let mut result = ScenarioResult {
+        name: scenario.name.clone(),
+        passed: false,
+        verifier_result: None,
+        matched_policy: None,
+        failed_checks: Vec::new(),
+        verifier_editor: None,
+        error: None,
+    };
+
+    let verifier = match token {
+        Some(token) => token.verify(root),
+        None => Verifier::new().map_err(error::Token::FailedLogic),
+    };
+    let mut verifier = match verifier {
+        Ok(verifier) => verifier,
+        Err(e) => {
+            result.error = Some(format!("could not create verifier: {:?}", e));
+            return result;
+        }
+    };
+
+    let mut biscuit_result = BiscuitResult::default();
+    if let Err(e) = add_time_fact(&mut verifier, current_time) {
+        biscuit_result.error = Some(e);
+    }
+    if let Err(e) = add_ambient_facts(&mut verifier, &scenario.ambient) {
+        biscuit_result.error = Some(e);
+    }
+
+    run_verifier(&mut biscuit_result, verifier, &scenario.verifier_code, limits, blocks, &HashMap::new(), &[]);
+
+    result.passed = matches!(
+        biscuit_result.verifier_result.as_ref().map(|result| &result.status),
+        Some(VerifierStatus::Success { .. })
+    );
+    result.failed_checks = biscuit_result.verifier_errors.iter()
+        .filter(|error| error.kind == "failed_check")
+        .filter_map(|error| error.rule.clone())
+        .collect();
+    result.verifier_result = biscuit_result.verifier_result;
+    result.matched_policy = biscuit_result.matched_policy;
+    result.verifier_editor = biscuit_result.verifier_editor;
+    result.error = biscuit_result.error;
+    result
+}
+
+// joins the snippets into one verifier code, returning the line each one
+// starts at. A `;` is added after snippets that do not end their last
+// statement, so it does not run into the next snippet. For the same reason
+// a `/*` left open is closed at the end of its snippet, see
+// `get_unclosed_comment_error`
+fn join_verifier_blocks(blocks: &[String]) -> (String, Vec<usize>) {
+    let mut code = String::new();
+    let mut start_lines = Vec::new();
+    for block in blocks.iter() {
+        start_lines.push(code.matches('\n').count());
+        let block = match get_unclosed_comment_error(block) {
+            Some(_) => format!("{}*/", block),
+            None => block.clone(),
+        };
+        let block = block.as_str();
+
+        // end of the last statement, before the trailing comments
+        let comments = get_comments(block);
+        let mut end = block.trim_end().len();
+        while let Some((offset, _)) = comments.iter().find(|(offset, comment)| offset + comment.len() == end) {
+            end = block[..*offset].trim_end().len();
+        }
+
+        code.push_str(&block[..end]);
+        if end > 0 && !block[..end].ends_with(';') {
+            code.push(';');
+        }
+        code.push_str(&block[end..]);
+        code.push('\n');
+    }
+
+    (code, start_lines)
+}
+
+// an unclosed comment is only a warning at the end of the input, but in a
+// snippet it would have hidden the next snippets
+fn get_unclosed_comment_error(block: &str) -> Option<ParseError> {
+    let (offset, _) = get_comments(block).pop().filter(|(_, comment)| {
+        comment.starts_with("/*") && (comment.len() < 4 || !comment.ends_with("*/"))
+    })?;
+    let span = &block[offset..];
+
+    Some(ParseError {
+        message: "this comment is not closed before the end of the snippet".to_string(),
+        position: get_position(block, span),
+        component: get_component(block, span).to_string(),
+        severity: Severity::Error,
+        code: ErrorCode::UnclosedComment,
+        snippet: get_snippet(span),
+    })
+}
+
+// gives each snippet of a joined code the elements of the editor that start
+// in it, with lines counted from the start of the snippet
+fn split_editor(editor: &Editor, start_lines: &[usize]) -> Vec<Editor> {
+    let snippet = |position: &SourcePosition| start_lines.iter()
+        .rposition(|line| *line <= position.line_start)
+        .unwrap_or(0);
+    let shift = |position: &SourcePosition, index: usize| SourcePosition::new(
+        position.line_start - start_lines[index],
+        position.column_start,
+        position.line_end - start_lines[index],
+        position.column_end,
+    );
+
+    let mut editors = vec![Editor::default(); start_lines.len()];
+    for error in editor.errors.iter() {
+        let index = snippet(&error.position);
+        editors[index].errors.push(ParseError { position: shift(&error.position, index), ..error.clone() });
+    }
+    for warning in editor.warnings.iter() {
+        let index = snippet(&warning.position);
+        editors[index].warnings.push(ParseError { position: shift(&warning.position, index), ..warning.clone() });
+    }
+    for marker in editor.markers.iter() {
+        let index = snippet(&marker.position);
+        editors[index].markers.push(Marker { position: shift(&marker.position, index), ..marker.clone() });
+    }
+    for element in editor.elements.iter() {
+        let index = snippet(&element.position);
+        editors[index].elements.push(Element { position: shift(&element.position, index), ..element.clone() });
+    }
+
+    editors
+}
+
+#[wasm_bindgen]
+pub fn verify_token(token_b64: &str, root_public_key_hex: &str, verifier_code: &str) -> JsBiscuitResult {
+    let token = Biscuit::from_base64(strip_armor(token_b64));
+    let result = verify_token_inner(token, &[root_public_key_hex.to_string()], verifier_code);
+
+    to_js(&result.with_summary())
+}
+
+/// same as `verify_token`, for a token that is not base64 encoded
+#[wasm_bindgen]
+pub fn verify_token_bytes(token: &[u8], root_public_key_hex: &str, verifier_code: &str) -> JsBiscuitResult {
+    let result = verify_token_inner(Biscuit::from(token), &[root_public_key_hex.to_string()], verifier_code);
+
+    to_js(&result.with_summary())
+}
+
+/// verifies a token that could be signed by any of the hex encoded `root_public_keys`
+#[wasm_bindgen]
+pub fn verify_token_with_keys(
+    token_b64: &str,
+    root_public_keys: &JsStrings,
+    verifier_code: &str,
+) -> JsBiscuitResult {
+    let result = match from_js::<Vec<String>>(root_public_keys) {
+        Ok(root_public_keys) => {
+            verify_token_inner(Biscuit::from_base64(strip_armor(token_b64)), &root_public_keys, verifier_code)
+        },
+        Err(e) => BiscuitResult { error: Some(e), ..Default::default() },
+    };
+
+    to_js(&result.with_summary())
+}
+
+#[derive(Default, Serialize, Deserialize, Tsify)]
+struct SignatureResult {
+    pub valid: bool,
+    pub block_count: usize,
+    pub error: Option<String>,
+}
+
+/// checks that the token's signatures are valid and that it was signed by the
+/// hex encoded root public key, without running a verifier
+#[wasm_bindgen]
+pub fn check_signature(token_b64: &str, root_public_key_hex: &str) -> JsSignatureResult {
+    to_js(&check_signature_inner(token_b64, root_public_key_hex))
+}
+
+fn check_signature_inner(token_b64: &str, root_public_key_hex: &str) -> SignatureResult {
+    let root = match parse_public_key(root_public_key_hex) {
+        Ok(root) => root,
+        Err(e) => return SignatureResult { error: Some(format!("invalid root public key: {}", e)), ..Default::default() },
+    };
+
+    // the signatures of all the blocks are verified when deserializing
+    let token = match Biscuit::from_base64(strip_armor(token_b64)) {
+        Ok(token) => token,
+        Err(e) => return SignatureResult { error: Some(format!("could not decode token: {:?}", e)), ..Default::default() },
+    };
+
+    let block_count = token.block_count();
+    match token.check_root_key(root) {
+        Ok(()) => SignatureResult { valid: true, block_count, error: None },
+        Err(e) => SignatureResult { valid: false, block_count, error: Some(format!("{:?}", e)) },
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Tsify)]
+struct TokenDiff {
+    pub root_key_equal: bool,
+    /// one entry per block index present in either token, authority first
+    pub blocks: Vec<BlockDiff>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Tsify)]
+struct BlockDiff {
+    pub index: usize,
+    /// true if both tokens contain the same serialized block
+    pub identical: bool,
+    /// true if both blocks are signed with the same key
+    pub key_equal: bool,
+    pub facts: StatementDiff,
+    pub rules: StatementDiff,
+    pub checks: StatementDiff,
+}
+
+/// statements as datalog, `added` being the ones only in the second token
+#[derive(Default, Serialize, Deserialize, Tsify)]
+struct StatementDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub common: Vec<String>,
+}
+
+impl StatementDiff {
+    fn new(a: Vec<String>, b: Vec<String>) -> Self {
+        let mut diff = StatementDiff::default();
+        for statement in a.iter() {
+            if b.contains(statement) {
+                diff.common.push(statement.clone());
+            } else {
+                diff.removed.push(statement.clone());
+            }
+        }
+        diff.added = b.into_iter().filter(|statement| !a.contains(statement)).collect();
+
+        diff
+    }
+}
+
+/// compares the blocks of two tokens, to review how the second one was attenuated
+#[wasm_bindgen]
+pub fn diff_tokens(a_b64: &str, b_b64: &str) -> JsTokenDiff {
+    to_js(&diff_tokens_inner(a_b64, b_b64))
+}
+
+fn diff_tokens_inner(a_b64: &str, b_b64: &str) -> TokenDiff {
+    let decode = |token_b64: &str, name: &str| Biscuit::from_base64(strip_armor(token_b64))
+        .map_err(|e| format!("could not decode token {}: {:?}", name, e));
+    let (a, b) = match (decode(a_b64, "a"), decode(b_b64, "b")) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => return TokenDiff { error: Some(e), ..Default::default() },
+    };
+    let (a_container, b_container) = match (a.container(), b.container()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return TokenDiff { error: Some("sealed tokens cannot be compared".to_string()), ..Default::default() },
+    };
+
+    let serialized = |container: &SerializedBiscuit| -> Vec<Vec<u8>> {
+        std::iter::once(&container.authority).chain(container.blocks.iter()).cloned().collect()
+    };
+    let (a_serialized, b_serialized) = (serialized(a_container), serialized(b_container));
+    let (a_blocks, b_blocks) = (get_token_blocks(&a), get_token_blocks(&b));
+    let (a_symbols, b_symbols) = (get_token_symbols(&a_blocks), get_token_symbols(&b_blocks));
+
+    let blocks = (0..a_blocks.len().max(b_blocks.len())).map(|index| {
+        let (a_block, b_block) = (a_blocks.get(index), b_blocks.get(index));
+        let statements = |block: Option<&TokenBlock>, symbols: &SymbolTable| match block {
+            Some(block) => (
+                block.facts.iter().map(|fact| predicate_code(&builder::Fact::convert_from(fact, symbols).0)).collect(),
+                block.rules.iter().map(|rule| rule_code(&builder::Rule::convert_from(rule, symbols))).collect(),
+                block.checks.iter().map(|check| check_code(&builder::Check::convert_from(check, symbols))).collect(),
+            ),
+            None => (Vec::new(), Vec::new(), Vec::new()),
+        };
+        let (a_facts, a_rules, a_checks) = statements(a_block, &a_symbols);
+        let (b_facts, b_rules, b_checks) = statements(b_block, &b_symbols);
+
+        let a_key = a_container.keys.get(index).map(|key| key.to_bytes());
+        let b_key = b_container.keys.get(index).map(|key| key.to_bytes());
+
+        BlockDiff {
+            index,
+            identical: a_block.is_some()
+                && b_block.is_some()
+                && a_serialized.get(index) == b_serialized.get(index),
+            key_equal: a_key.is_some() && a_key == b_key,
+            facts: StatementDiff::new(a_facts, b_facts),
+            rules: StatementDiff::new(a_rules, b_rules),
+            checks: StatementDiff::new(a_checks, b_checks),
+        }
+    }).collect();
+
+    TokenDiff {
+        root_key_equal: a_container.keys.first().map(|key| key.to_bytes())
+            == b_container.keys.first().map(|key| key.to_bytes()),
+        blocks,
+        error: None,
+    }
+}
+
+fn verify_token_inner(
+    token: Result<Biscuit, error::Token>,
+    root_public_keys: &[String],
+    verifier_code: &str,
+) -> BiscuitResult {
+    let mut biscuit_result = BiscuitResult::default();
+
+    let token = match token {
+        Ok(token) => token,
+        Err(e) => {
+            error!("could not decode token: {:?}", e);
+            biscuit_result.error = Some(format!("could not decode token: {:?}", e));
+            return biscuit_result;
+        }
+    };
+
+    set_token_info(&mut biscuit_result, &token, false);
+
+    let mut candidates = Vec::new();
+    for (index, key) in root_public_keys.iter().enumerate() {
+        match parse_public_key(key) {
+            Ok(root) => candidates.push(root),
+            Err(e) => {
+                biscuit_result.error = Some(format!("invalid root public key at index {}: {}", index, e));
+                return biscuit_result;
+            }
+        }
+    }
+
+    // stops at the first key that validates the signature
+    let (index, root) = match candidates
+        .into_iter()
+        .enumerate()
+        .find(|(_, root)| token.check_root_key(*root).is_ok())
+    {
+        Some(found) => found,
+        None => {
+            biscuit_result.error = Some("no root public key matched the token's signature".to_string());
+            return biscuit_result;
+        }
+    };
+    biscuit_result.root_key_index = Some(index);
+    biscuit_result.root_public_key = hex::encode(root.to_bytes());
+    biscuit_result.verifier_trusted_keys = vec![biscuit_result.root_public_key.clone()];
+
+    let mut verifier = match token.verify(root) {
+        Ok(verifier) => verifier,
+        Err(e) => {
+            error!("could not verify token: {:?}", e);
+            biscuit_result.error = Some(format!("could not verify token: {:?}", e));
+            return biscuit_result;
+        }
+    };
+
+    // the blocks were not checked when the token was made
+    let (_, rules, privileged_rules, _, _) = verifier.dump();
+    if let Some(rule) = privileged_rules.iter().chain(rules.iter()).find(|rule| rule.1.is_empty()) {
+        biscuit_result.error = Some(format!("{}: {}", RULE_WITHOUT_PREDICATES, rule));
+        return biscuit_result;
+    }
+
+    if let Err(e) = add_time_fact(&mut verifier, None) {
+        biscuit_result.error = Some(e);
+    }
+
+    // there is no source code for the token's blocks, so no markers can be
+    // attached to their checks
+    let mut blocks = Vec::new();
+    let fact_origins = get_fact_origins(&token);
+
+    run_verifier(
+        &mut biscuit_result,
+        verifier,
+        verifier_code,
+        VerifierLimitsInput::default().to_limits(),
+        &mut blocks,
+        &fact_origins,
+        &[],
+    );
+
+    biscuit_result
+}
+
+/// results of `run_authorizer`, named like the verifier fields of `BiscuitResult`
+#[derive(Default, Serialize, Deserialize, Tsify)]
+struct AuthorizerResult {
+    pub verifier_editor: Editor,
+    pub verifier_result: Option<VerifierResult>,
+    pub verifier_errors: Vec<VerifierError>,
+    pub matched_policy: Option<MatchedPolicy>,
+    pub checks: Vec<CheckResult>,
+    pub policies: Vec<PolicyEvaluation>,
+    pub verifier_duration_millis: f64,
+    pub verifier_world: Vec<Fact>,
+    pub verifier_world_rules: Vec<String>,
+    pub verifier_world_checks: Vec<String>,
+    pub verifier_world_code: Option<String>,
+    pub rule_derivations: Vec<RuleDerivation>,
+    /// for `restore_verifier_snapshot`, the snapshot printed as verifier code,
+    /// which is what `verifier_editor` positions refer to
+    pub verifier_code: Option<String>,
+    pub error: Option<String>,
+}
+
+/// runs the verifier code on its own, without any token, to try out datalog
+/// before putting it in blocks
+#[wasm_bindgen]
+pub fn run_authorizer(datalog: &str) -> JsAuthorizerResult {
+    to_js(&run_authorizer_inner(datalog))
+}
+
+fn run_authorizer_inner(datalog: &str) -> AuthorizerResult {
+    authorize(datalog, true)
+}
+
+/// runs a verifier saved in `verifier_snapshot` again, with the default
+/// limits. The snapshot is printed back as verifier code so the results
+/// can point at its statements
+#[wasm_bindgen]
+pub fn restore_verifier_snapshot(snapshot_b64: &str) -> JsAuthorizerResult {
+    to_js(&restore_verifier_snapshot_inner(snapshot_b64))
+}
+
+fn restore_verifier_snapshot_inner(snapshot_b64: &str) -> AuthorizerResult {
+    let verifier = base64::decode_config(snapshot_b64.trim(), base64::URL_SAFE)
+        .map_err(|e| format!("invalid base64: {}", e))
+        .and_then(|bytes| Verifier::from(&bytes).map_err(|e| format!("could not load verifier: {:?}", e)));
+    let verifier = match verifier {
+        Ok(verifier) => verifier,
+        Err(e) => return AuthorizerResult { error: Some(e), ..Default::default() },
+    };
+
+    let (mut facts, rules, privileged_rules, checks, policies) = verifier.dump();
+    facts.sort_by_cached_key(|fact| fact.to_string());
+    let code = world_code(&facts, privileged_rules.iter().chain(rules.iter()), &checks, &policies);
+
+    // the snapshot already has its `time` fact
+    AuthorizerResult {
+        verifier_code: Some(code.clone()),
+        ..authorize(&code, false)
+    }
+}
+
+fn authorize(datalog: &str, with_time: bool) -> AuthorizerResult {
+    let mut verifier = match Verifier::new() {
+        Ok(verifier) => verifier,
+        Err(e) => {
+            error!("could not create verifier: {:?}", e);
+            return AuthorizerResult {
+                error: Some(format!("could not create verifier: {:?}", e)),
+                ..Default::default()
+            };
+        }
+    };
+
+    let mut biscuit_result = BiscuitResult::default();
+    if with_time {
+        if let Err(e) = add_time_fact(&mut verifier, None) {
+            biscuit_result.error = Some(e);
+        }
+    }
+    run_verifier(
+        &mut biscuit_result,
+        verifier,
+        datalog,
+        VerifierLimitsInput::default().to_limits(),
+        &mut [],
+        &HashMap::new(),
+        &[],
+    );
+
+    let mut verifier_editor = biscuit_result.verifier_editor.unwrap_or_default();
+    verifier_editor.warnings = get_warnings(&[datalog], &[]).pop().unwrap_or_default();
+
+    AuthorizerResult {
+        verifier_editor,
+        verifier_result: biscuit_result.verifier_result,
+        verifier_errors: biscuit_result.verifier_errors,
+        matched_policy: biscuit_result.matched_policy,
+        checks: biscuit_result.checks,
+        policies: biscuit_result.policies,
+        verifier_duration_millis: biscuit_result.verifier_duration_millis,
+        verifier_world: biscuit_result.verifier_world,
+        verifier_world_rules: biscuit_result.verifier_world_rules,
+        verifier_world_checks: biscuit_result.verifier_world_checks,
+        verifier_world_code: biscuit_result.verifier_world_code,
+        rule_derivations: biscuit_result.rule_derivations,
+        verifier_code: None,
+        error: biscuit_result.error,
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Tsify)]
+struct QueryResult {
+    pub facts: Vec<Fact>,
+    pub error: Option<String>,
+}
+
+/// runs a query against a world saved from `verifier_world_code`, without
+/// building the token and running the checks again. The saved world already
+/// contains the facts generated by its rules, so only its facts are loaded
+#[wasm_bindgen]
+pub fn query_world(world_code: &str, query: &str) -> JsQueryResult {
+    to_js(&query_world_inner(world_code, query))
+}
+
+fn query_world_inner(world_code: &str, query: &str) -> QueryResult {
+    let error = |e: String| QueryResult { error: Some(e), ..Default::default() };
+
+    let world = match parse_datalog(if is_empty_block(world_code) { "" } else { world_code }) {
+        Ok((_, world)) => world,
+        Err(errors) => return error(format!("invalid world: {:?}", errors)),
+    };
+    let mut verifier = match Verifier::new() {
+        Ok(verifier) => verifier,
+        Err(e) => return error(format!("could not create verifier: {:?}", e)),
+    };
+    for (_, fact) in world.facts.into_iter() {
+        if let Err(e) = verifier.add_fact(fact) {
+            return error(format!("invalid world: {:?}", e));
+        }
+    }
+
+    let limits = VerifierLimitsInput::default().to_limits();
+    let facts: Result<Vec<builder::Fact>, String> = parse_query(query, &HashMap::new())
+        .and_then(|rule| verifier.query_with_limits(rule, limits).map_err(|e| format!("{:?}", e)));
+    match facts {
+        Ok(mut facts) => {
+            facts.sort_by_cached_key(|fact| fact.to_string());
+            QueryResult { facts: facts.drain(..).map(Fact::from).collect(), error: None }
+        },
+        Err(e) => error(e),
+    }
+}
+
+/// how `append_blocks` generates the blocks, see the `BiscuitQuery` fields
+/// of the same names
+#[derive(Clone, Copy, Default)]
+struct AppendOptions {
+    pub deterministic: bool,
+    pub block_seed: Option<u64>,
+    pub fail_fast: bool,
+}
+
+// parses the code of each block and appends it to the token, collecting
+// the editors and check positions
+fn append_blocks(
+    mut token: Biscuit,
+    codes: &[Option<&str>],
+    rng: &mut StdRng,
+    options: AppendOptions,
+    biscuit_result: &mut BiscuitResult,
+    blocks: &mut Vec<Block>,
+) -> Biscuit {
+    let AppendOptions { deterministic, block_seed, fail_fast } = options;
+    let mut key_rng: Option<StdRng> = block_seed.map(SeedableRng::seed_from_u64);
+
+    for code in codes.iter() {
+        // disabled and empty blocks are not appended, but keep their place
+        // in the editors
+        let code = match code {
+            Some(code) if !is_empty_block(code) => *code,
+            _ => {
+                blocks.push(Block { enabled: false, ..Default::default() });
+                biscuit_result.token_blocks.push(Editor::default());
+                continue;
+            },
+        };
+        let mut editor = Editor::default();
+        let mut block = Block::default();
+
+        let seed = block_seed.unwrap_or(0).wrapping_add(token.block_count() as u64);
+        let mut block_rng: StdRng = SeedableRng::seed_from_u64(seed);
+        let rng = if deterministic { &mut block_rng } else { &mut *rng };
+
+        let temp_keypair = match key_rng.as_mut() {
+            Some(key_rng) if !deterministic => KeyPair::new_with_rng(key_rng),
+            _ => KeyPair::new_with_rng(rng),
+        };
+        let mut builder = token.create_block();
+
+        match parse_datalog(code) {
+            Err(errors) => {
+                error!("error: {:?}", errors);
+                editor.errors = get_parse_errors(code, errors);
+                if fail_fast {
+                    biscuit_result.error = Some(parse_failure(biscuit_result.token_blocks.len()));
+                    biscuit_result.token_blocks.push(editor);
+                    return token;
+                }
+            },
+            Ok((_, block_parsed)) => {
+                for (i, fact) in block_parsed.facts.iter() {
+                    match builder.add_fact(fact.clone()) {
+                        Err(e) => editor.errors.push(get_token_error(code, i, e)),
+                        Ok(()) => block.facts.push(get_position(code, i)),
+                    }
+                }
+
+                for (i, rule) in block_parsed.rules.iter() {
+                    if rule.1.is_empty() {
+                        editor.errors.push(get_rule_error(code, i));
+                        continue;
+                    }
+                    match builder.add_rule(rule.clone()) {
+                        Err(e) => editor.errors.push(get_token_error(code, i, e)),
+                        Ok(()) => block.rules.push((get_position(code, i), rule.clone())),
+                    }
+                }
+
+                for (i, check) in block_parsed.checks.iter() {
+                    match builder.add_check(check.clone()) {
+                        Err(e) => editor.errors.push(get_token_error(code, i, e)),
+                        Ok(()) => {
+                            let position = get_position(code, i);
+                            block.checks.push((position, check.clone(), None));
+                        }
+                    }
+                }
+            }
+        }
+
+        // the token is left unchanged if the block cannot be added
+        match token.append_with_rng(rng, &temp_keypair, builder) {
+            Ok(new_token) => token = new_token,
+            Err(e) => {
+                let index = biscuit_result.token_blocks.len();
+                error!("could not append block {}: {:?}", index, e);
+                biscuit_result.error = Some(format!("could not append block {}: {:?}", index, e));
+                block.enabled = false;
+            }
+        }
+
+        editor.elements = block.elements();
+        blocks.push(block);
+        biscuit_result.token_blocks.push(editor);
+    }
+
+    token
+}
+
+#[derive(Default, Serialize, Deserialize, Tsify)]
+struct TokenResult {
+    pub token_blocks: Vec<Editor>,
+    pub token_content: String,
+    pub serialized: Option<String>,
+    pub serialized_hex: Option<String>,
+    pub token_size: usize,
+    pub error: Option<String>,
+}
+
+/// generates a token from the blocks' source code, without running a verifier
+#[wasm_bindgen]
+pub fn build_token(blocks: &JsStrings) -> JsTokenResult {
+    let result = match from_js(blocks) {
+        Ok(blocks) => build_token_inner(blocks),
+        Err(e) => TokenResult { error: Some(e), ..Default::default() },
+    };
+
+    to_js(&result)
+}
+
+fn build_token_inner(token_blocks: Vec<String>) -> TokenResult {
+    let result = execute_inner(BiscuitQuery {
+        token_blocks,
+        ..Default::default()
+    });
+
+    TokenResult {
+        token_blocks: result.token_blocks,
+        token_content: result.token_content,
+        serialized: result.serialized,
+        serialized_hex: result.serialized_hex,
+        token_size: result.token_size,
+        error: result.error,
+    }
+}
+
+/// appends blocks to an existing base64 token
+///
+/// the editors in `token_blocks` correspond to `new_blocks`. The temporary
+/// keys and signatures of the new blocks use the system randomness
+#[wasm_bindgen]
+pub fn attenuate(token_b64: &str, new_blocks: &JsStrings) -> JsBiscuitResult {
+    let result = match from_js::<Vec<String>>(new_blocks) {
+        Ok(new_blocks) => attenuate_inner(token_b64, &new_blocks),
+        Err(e) => BiscuitResult { error: Some(e), ..Default::default() },
+    };
+
+    to_js(&result.with_summary())
+}
+
+fn attenuate_inner(token_b64: &str, new_blocks: &[String]) -> BiscuitResult {
+    let mut biscuit_result = BiscuitResult::default();
+
+    let token = match Biscuit::from_base64(strip_armor(token_b64)) {
+        Ok(token) => token,
+        Err(e) => {
+            error!("could not decode token: {:?}", e);
+            biscuit_result.error = Some(format!("could not decode token: {:?}", e));
+            return biscuit_result;
+        }
+    };
+
+    let mut rng: StdRng = SeedableRng::from_entropy();
+    let mut blocks = Vec::new();
+    let codes: Vec<Option<&str>> = new_blocks.iter().map(|code| Some(code.as_str())).collect();
+    let token = append_blocks(token, &codes, &mut rng, AppendOptions::default(), &mut biscuit_result, &mut blocks);
+
+    match token.to_vec() {
+        Ok(v) => {
+            biscuit_result.serialized = Some(base64::encode_config(&v[..], base64::URL_SAFE));
+            biscuit_result.serialized_hex = Some(hex::encode(&v));
+        },
+        Err(e) => {
+            error!("could not serialize token: {:?}", e);
+            biscuit_result.error = Some(format!("could not serialize token: {:?}", e));
+        }
+    }
+    set_token_info(&mut biscuit_result, &token, false);
+
+    biscuit_result
+}
+
+// adds the `time(#ambient, $date)` fact used by time based checks
+fn add_time_fact(verifier: &mut Verifier, current_time: Option<&str>) -> Result<(), String> {
+    let time = match current_time {
+        Some(current_time) => chrono::DateTime::parse_from_rfc3339(current_time.trim())
+            .map(SystemTime::from)
+            .map_err(|e| format!("invalid current_time \"{}\": {}", current_time, e))?,
+        None => now(),
+    };
+
+    verifier
+        .add_fact(builder::fact("time", &[builder::s("ambient"), builder::date(&time)]))
+        .map_err(|e| format!("could not add the time fact: {:?}", e))
+}
+
+fn add_ambient_facts(verifier: &mut Verifier, ambient: &AmbientContext) -> Result<(), String> {
+    if let Some(resource) = ambient.resource.as_ref() {
+        verifier.add_resource(resource);
+    }
+
+    if let Some(operation) = ambient.operation.as_ref() {
+        verifier.add_operation(operation);
+    }
+
+    let mut invalid = Vec::new();
+    for fact in ambient.extra_facts.iter() {
+        if verifier.add_fact(fact.as_str()).is_err() {
+            invalid.push(format!("\"{}\"", fact));
+        }
+    }
+
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("invalid ambient facts: {}", invalid.join(", ")))
+    }
+}
+
+fn add_json_facts(verifier: &mut Verifier, facts: &[FactInput]) -> Result<(), String> {
+    let mut invalid = Vec::new();
+    for (index, fact) in facts.iter().enumerate() {
+        let added = fact.to_fact().and_then(|fact| verifier.add_fact(fact).map_err(|e| format!("{:?}", e)));
+        if let Err(e) = added {
+            invalid.push(format!("{} ({}): {}", index, fact.name, e));
+        }
+    }
+
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("invalid world facts: {}", invalid.join(", ")))
+    }
+}
+
+// SystemTime::now() is not available in the browser
+fn now() -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(now_millis() as u64)
+}
+
+// Instant is not available either, so durations are measured with the clock
+#[cfg(target_arch = "wasm32")]
+fn now_millis() -> f64 {
+    js_sys::Date::now()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn now_millis() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64() * 1000.0).unwrap_or(0.0)
+}
+
+#[derive(Serialize, Deserialize, Tsify)]
+struct KeyPairResult {
+    /// hex encoded, to be kept secret
+    pub private_key: String,
+    /// hex encoded, in the same format as `root_public_key`
+    pub public_key: String,
+}
+
+/// generates a root keypair, from the seed if there is one, otherwise from the
+/// browser's `crypto.getRandomValues`
+#[wasm_bindgen]
+pub fn generate_keypair(seed: Option<u64>) -> JsKeyPairResult {
+    to_js(&generate_keypair_inner(seed))
+}
+
+/// symbols every token starts with, in index order: the tokens' own symbols
+/// are numbered after them
+#[wasm_bindgen]
+pub fn default_symbols() -> JsStrings {
+    to_js(&default_symbol_table().symbols)
+}
+
+const ARMOR_BEGIN: &str = "-----BEGIN BISCUIT-----";
+const ARMOR_END: &str = "-----END BISCUIT-----";
+
+fn armor(token_b64: &str) -> String {
+    let mut armored = format!("{}\n", ARMOR_BEGIN);
+    // base64 is ASCII, so the chunks are valid strings
+    for line in token_b64.as_bytes().chunks(64) {
+        armored.push_str(&String::from_utf8_lossy(line));
+        armored.push('\n');
+    }
+    armored.push_str(ARMOR_END);
+    armored.push('\n');
+    armored
+}
+
+// accepts the output of `armor` as well as plain base64, with the line
+// breaks and spacing of copy pasted text
+fn strip_armor(token: &str) -> String {
+    let token = token.trim();
+    let token = token.strip_prefix(ARMOR_BEGIN)
+        .and_then(|token| token.trim_end().strip_suffix(ARMOR_END))
+        .unwrap_or(token);
+    token.split_whitespace().collect()
+}
+
+// root keys are 32 bytes compressed Ristretto points, as printed in `root_public_key`
+fn parse_public_key(key: &str) -> Result<PublicKey, String> {
+    let bytes = hex::decode(key.trim()).map_err(|e| format!("expected a hex encoded public key: {}", e))?;
+    if bytes.len() != 32 {
+        return Err(format!("expected a 32 bytes hex encoded public key, got {} bytes", bytes.len()));
+    }
+
+    PublicKey::from_bytes(&bytes).ok_or_else(|| "the public key is not a valid curve point".to_string())
+}
+
+fn parse_private_key(key: &str) -> Option<PrivateKey> {
+    hex::decode(key.trim()).ok().and_then(|bytes| PrivateKey::from_bytes(&bytes))
+}
+
+fn generate_keypair_inner(seed: Option<u64>) -> KeyPairResult {
+    let mut rng: StdRng = match seed {
+        Some(seed) => SeedableRng::seed_from_u64(seed),
+        None => SeedableRng::from_entropy(),
+    };
+    let keypair = KeyPair::new_with_rng(&mut rng);
+
+    KeyPairResult {
+        private_key: hex::encode(keypair.private().to_bytes()),
+        public_key: hex::encode(keypair.public().to_bytes()),
+    }
+}
+
+/// parses each block and reports syntax errors, without generating or verifying a token
+#[wasm_bindgen]
+pub fn lint(blocks: &JsStrings) -> JsEditors {
+    // there is no result to hold an error, so invalid input gets no editors
+    let result = match from_js::<Vec<String>>(blocks) {
+        Ok(blocks) => lint_inner(&blocks),
+        Err(e) => {
+            error!("{}", e);
+            Vec::new()
+        },
+    };
+
+    to_js(&result)
+}
+
+#[derive(Default, Serialize, Deserialize, Tsify)]
+struct FormatResult {
+    /// None if the source has errors
+    pub formatted: Option<String>,
+    pub errors: Vec<ParseError>,
+}
+
+#[derive(Default, Serialize, Deserialize, Tsify)]
+struct EvalResult {
+    pub value: Option<Term>,
+    pub error: Option<String>,
+}
+
+/// evaluates a single expression, like `1 + 2 * 3` or `$s.length()`, with
+/// the verifier's engine. `variables` gives the values of the `$` variables,
+/// named without the `$`
+#[wasm_bindgen]
+pub fn eval_expression(expr: &str, variables: &JsTermValues) -> JsEvalResult {
+    // a missing map is the same as an empty one
+    let variables = if variables.is_undefined() || variables.is_null() {
+        Ok(HashMap::new())
+    } else {
+        from_js(variables)
+    };
+
+    let result = match variables {
+        Ok(variables) => eval_expression_inner(expr, &variables),
+        Err(e) => EvalResult { error: Some(e), ..Default::default() },
+    };
+
+    to_js(&result)
+}
+
+fn eval_expression_inner(expr: &str, variables: &HashMap<String, TermValue>) -> EvalResult {
+    let error = |e: String| EvalResult { error: Some(e), ..Default::default() };
+
+    // the expression parser is not public, but a check can be made of a
+    // single expression
+    let check = match builder::Check::try_from(format!("check if {}", expr.trim().trim_end_matches(';')).as_str()) {
+        Ok(check) => check,
+        Err(e) => return error(format!("invalid expression: {:?}", e)),
+    };
+    let expression = match check.queries.as_slice() {
+        [builder::Rule(_, body, expressions)] if body.is_empty() && expressions.len() == 1 => &expressions[0],
+        _ => return error("expected a single expression, without predicates".to_string()),
+    };
+
+    let mut symbols = default_symbol_table();
+    let expression = expression.convert(&mut symbols);
+
+    let mut values = HashMap::new();
+    for (name, value) in variables.iter() {
+        let term = match value.to_term() {
+            Ok(term) => term,
+            Err(e) => return error(format!("variable \"{}\": {}", name, e)),
+        };
+        let id = symbols.insert(name.trim_start_matches('$')) as u32;
+        values.insert(id, term.convert(&mut symbols));
+    }
+
+    match expression.evaluate(&values) {
+        Some(id) => EvalResult {
+            value: Some(Term::from(builder::Term::convert_from(&id, &symbols))),
+            error: None,
+        },
+        None => error("the expression could not be evaluated: a variable is unbound or an operation got the wrong types".to_string()),
+    }
+}
+
+/// prints the source back with one statement per line, in the order they
+/// are written, and the spacing of the builders.
+///
+/// The parser drops comments, so they are found beforehand and each one is
+/// printed on its own line right before the first statement that starts
+/// after it. A comment at the end of a statement's line thus moves above the
+/// next statement, and comments after the last statement stay at the end
+#[wasm_bindgen]
+pub fn format_datalog(source: &str) -> JsFormatResult {
+    to_js(&format_datalog_inner(source))
+}
+
+fn format_datalog_inner(source: &str) -> FormatResult {
+    let parsed = match parse_datalog(if is_empty_block(source) { "" } else { source }) {
+        Ok((_, parsed)) => parsed,
+        Err(errors) => return FormatResult { formatted: None, errors: get_parse_errors(source, errors) },
+    };
+
+    let mut statements: Vec<(usize, String)> = Vec::new();
+    statements.extend(parsed.facts.iter().map(|(span, fact)| (source.offset(span), predicate_code(&fact.0))));
+    statements.extend(parsed.rules.iter().map(|(span, rule)| (source.offset(span), rule_code(rule))));
+    statements.extend(parsed.checks.iter().map(|(span, check)| (source.offset(span), check_code(check))));
+    statements.extend(parsed.policies.iter().map(|(span, policy)| (source.offset(span), policy_code(policy))));
+    let statements = statements.into_iter().map(|(offset, statement)| (offset, format!("{};", statement)));
+
+    let mut lines: Vec<(usize, String)> = get_comments(source).into_iter()
+        .map(|(offset, comment)| (offset, comment.to_string()))
+        .chain(statements)
+        .collect();
+    lines.sort_by_key(|(offset, _)| *offset);
+
+    let formatted = lines.iter().map(|(_, line)| format!("{}\n", line)).collect();
+    FormatResult { formatted: Some(formatted), errors: Vec::new() }
+}
+
+// `//` and `/* */` comments with their offset, skipping the ones in strings
+fn get_comments(source: &str) -> Vec<(usize, &str)> {
+    let mut comments = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut index = 0;
+
+    while index < source.len() {
+        let rest = &source[index..];
+        if in_string {
+            match rest.chars().next() {
+                Some('\\') if !escaped => escaped = true,
+                Some('"') if !escaped => in_string = false,
+                _ => escaped = false,
+            }
+        } else if rest.starts_with("//") {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            comments.push((index, rest[..end].trim_end()));
+            index += end;
+            continue;
+        } else if let Some(content) = rest.strip_prefix("/*") {
+            // `/*/` does not close the comment it opens
+            let end = content.find("*/").map(|end| end + 4).unwrap_or(rest.len());
+            comments.push((index, &rest[..end]));
+            index += end;
+            continue;
+        } else if rest.starts_with('"') {
+            in_string = true;
+        }
+
+        index += rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+    }
+
+    comments
+}
+
+fn lint_inner(blocks: &[String]) -> Vec<Editor> {
+    let codes: Vec<&str> = blocks.iter().map(|code| code.as_str()).collect();
+    let mut warnings = get_warnings(&codes, &[]);
+
+    blocks.iter().zip(warnings.drain(..)).map(|(code, warnings)| {
+        let mut editor = Editor { warnings, ..Default::default() };
+        if is_empty_block(code) {
+            return editor;
+        }
+
+        if let Err(errors) = parse_datalog(code) {
+            editor.errors = get_parse_errors(code, errors);
+        }
+        editor
+    }).collect()
+}
+
+fn set_warnings(biscuit_result: &mut BiscuitResult, query: &BiscuitQuery) {
+    // disabled blocks define nothing and get no warnings
+    let mut codes: Vec<&str> = query.token_blocks.iter()
+        .enumerate()
+        .map(|(index, code)| if query.block_enabled(index) { code.as_str() } else { "" })
+        .collect();
+    if let Some(verifier_code) = query.verifier_code.as_ref() {
+        codes.push(verifier_code);
+    }
+
+    let known: Vec<String> = query.ambient.iter()
+        .flat_map(|ambient| ambient.extra_facts.iter())
+        .filter_map(|fact| builder::Fact::try_from(fact.as_str()).ok())
+        .map(|fact| fact.0.name)
+        .chain(query.world_facts_json.iter().flatten().map(|fact| fact.name.clone()))
+        .collect();
+
+    biscuit_result.defined_predicates = get_defined_predicates(&codes, &known);
+
+    let mut warnings = get_warnings(&codes, &known);
+    if query.verifier_code.is_some() {
+        if let (Some(ed), Some(w)) = (biscuit_result.verifier_editor.as_mut(), warnings.pop()) {
+            ed.warnings = w;
+        }
+    }
+
+    for (ed, w) in biscuit_result.token_blocks.iter_mut().zip(warnings.drain(..)) {
+        ed.warnings = w;
+    }
+}
+
+// the parser rejects input made only of whitespace
+fn is_empty_block(code: &str) -> bool {
+    code.trim().is_empty()
+}
+
+// predicates provided by the verifier when it checks a request
+const AMBIENT_PREDICATES: &[&str] = &["resource", "operation", "time"];
+
+// names of the facts and rule heads of all the sources, sorted and without
+// duplicates. `known` lists predicates defined outside of the sources
+fn get_defined_predicates(codes: &[&str], known: &[String]) -> Vec<String> {
+    let mut defined: Vec<String> = known.to_vec();
+    for (_, source) in codes.iter().filter_map(|code| parse_datalog(code).ok()) {
+        defined.extend(source.facts.into_iter().map(|(_, fact)| fact.0.name));
+        defined.extend(source.rules.into_iter().map(|(_, rule)| rule.0.name));
+    }
+    defined.sort();
+    defined.dedup();
+
+    defined
+}
+
+// warnings of each source: the syntax errors the parser recovered from,
+// then the undefined predicates
+fn get_warnings(codes: &[&str], known: &[String]) -> Vec<Vec<ParseError>> {
+    codes.iter().zip(get_undefined_predicates(codes, known)).map(|(code, mut undefined)| {
+        let mut warnings = get_recovered_errors(code);
+        warnings.append(&mut undefined);
+        warnings
+    }).collect()
+}
+
+// looks for predicates used in rule, check and policy bodies that appear in
+// no fact nor rule head of any of the sources, returning warnings for each
+// source. `known` lists predicates defined outside of the sources
+fn get_undefined_predicates(codes: &[&str], known: &[String]) -> Vec<Vec<ParseError>> {
+    let parsed: Vec<_> = codes.iter().map(|code| parse_datalog(code).ok().map(|(_, parsed)| parsed)).collect();
+
+    let mut defined: Vec<&str> = AMBIENT_PREDICATES.to_vec();
+    defined.extend(known.iter().map(|name| name.as_str()));
+    for source in parsed.iter().flatten() {
+        defined.extend(source.facts.iter().map(|(_, fact)| fact.0.name.as_str()));
+        defined.extend(source.rules.iter().map(|(_, rule)| rule.0.name.as_str()));
+    }
+
+    codes.iter().zip(parsed.iter()).map(|(code, source)| {
+        let source = match source {
+            Some(source) => source,
+            None => return Vec::new(),
+        };
+
+        let mut bodies: Vec<(&str, &builder::Rule)> = Vec::new();
+        bodies.extend(source.rules.iter().map(|(span, rule)| (*span, rule)));
+        for (span, check) in source.checks.iter() {
+            bodies.extend(check.queries.iter().map(|rule| (*span, rule)));
+        }
+        for (span, policy) in source.policies.iter() {
+            bodies.extend(policy.queries.iter().map(|rule| (*span, rule)));
+        }
+
+        let mut warnings: Vec<ParseError> = Vec::new();
+        let mut reported: Vec<(&str, &str)> = Vec::new();
+        for (span, rule) in bodies {
+            for predicate in rule.1.iter() {
+                let name = predicate.name.as_str();
+                if defined.contains(&name) || reported.contains(&(span, name)) {
+                    continue;
+                }
+                reported.push((span, name));
+
+                let predicate_span = find_predicate(span, name).unwrap_or(span);
+                warnings.push(ParseError {
+                    message: format!("predicate \"{}\" is not defined by any fact or rule", name),
+                    position: get_position(code, predicate_span),
+                    component: get_component(code, span).to_string(),
+                    severity: Severity::Warning,
+                    code: ErrorCode::UndefinedPredicate,
+                    snippet: get_snippet(predicate_span),
+                });
+            }
+        }
+
+        warnings
+    }).collect()
+}
+
+// finds the first use of the predicate `name` in a statement
+fn find_predicate<'a>(statement: &'a str, name: &str) -> Option<&'a str> {
+    statement.match_indices(name).find(|(index, _)| {
+        let before = statement[..*index].chars().next_back();
+        let after = statement[index + name.len()..].trim_start().chars().next();
+        !before.map(|c| c.is_alphanumeric() || c == '_').unwrap_or(false) && after == Some('(')
+    }).map(|(index, _)| &statement[index..index + name.len()])
+}
+
+fn run_verifier(
+    biscuit_result: &mut BiscuitResult,
+    mut verifier: Verifier,
+    verifier_code: &str,
+    limits: VerifierLimits,
+    blocks: &mut [Block],
+    fact_origins: &HashMap<String, u32>,
+    disabled_policies: &[usize],
+) -> Option<Verifier> {
+    biscuit_result.verifier_editor = Some(Editor::default());
+    //info!("verifier source:\n{}", &verifier_code);
+
+    let verifier_result;
+
+    let res = parse_datalog(if is_empty_block(verifier_code) { "" } else { verifier_code });
+    if let Err(errors) = res {
+        let message = format!("errors: {:?}", errors);
+        biscuit_result.verifier_result = Some(VerifierResult {
+            status: VerifierStatus::Other { error: message.clone() },
+            message,
+        });
+        error!("error: {:?}", errors);
+        if let Some(ed) = biscuit_result.verifier_editor.as_mut() {
+            ed.errors = get_parse_errors(verifier_code, errors);
+        }
+
+        None
+    } else {
+        let mut verifier_block = Block::default();
+        let mut verifier_policies = Vec::new();
+        let mut disabled = Vec::new();
+
+        let (_, parsed) = res.unwrap();
+
+        let mut errors = Vec::new();
+
+        for (i, fact) in parsed.facts.iter() {
+            match verifier.add_fact(fact.clone()) {
+                Err(e) => errors.push(get_token_error(verifier_code, i, e)),
+                Ok(()) => verifier_block.facts.push(get_position(verifier_code, i)),
+            }
+        }
+
+        for (i, rule) in parsed.rules.iter() {
+            if rule.1.is_empty() {
+                errors.push(get_rule_error(verifier_code, i));
+                continue;
+            }
+            match verifier.add_rule(rule.clone()) {
+                Err(e) => errors.push(get_token_error(verifier_code, i, e)),
+                Ok(()) => verifier_block.rules.push((get_position(verifier_code, i), rule.clone())),
+            }
+        }
+
+        for (i, check) in parsed.checks.iter() {
+            match verifier.add_check(check.clone()) {
+                Err(e) => errors.push(get_token_error(verifier_code, i, e)),
+                Ok(()) => {
+                    let position = get_position(verifier_code, i);
+                    // checks are marked as success until they fail
+                    verifier_block.checks.push((position, check.clone(), None));
+                }
+            }
+        }
+
+        for (index, (i, policy)) in parsed.policies.iter().enumerate() {
+            let verifier_policy = VerifierPolicy {
+                index,
+                position: get_position(verifier_code, i),
+                source: i.to_string(),
+                policy: policy.clone(),
+            };
+
+            if disabled_policies.contains(&index) {
+                disabled.push(verifier_policy);
+                continue;
+            }
+
+            match verifier.add_policy(policy.clone()) {
+                Err(e) => errors.push(get_token_error(verifier_code, i, e)),
+                Ok(()) => verifier_policies.push(verifier_policy),
+            }
+        }
+
+        if let Some(ed) = biscuit_result.verifier_editor.as_mut() {
+            ed.errors = errors;
+            ed.elements = verifier_block.elements();
+            ed.elements.extend(verifier_policies.iter().chain(disabled.iter()).map(|policy| Element {
+                kind: "policy".to_string(),
+                position: policy.position.clone(),
+            }));
+        }
+
+        let start = now_millis();
+        verifier_result = verifier.verify_with_limits(limits.clone());
+        biscuit_result.verifier_duration_millis = now_millis() - start;
+
+        biscuit_result.verifier_snapshot = match verifier.save() {
+            Ok(snapshot) => Some(base64::encode_config(&snapshot[..], base64::URL_SAFE)),
+            Err(e) => {
+                error!("could not save verifier: {:?}", e);
+                None
+            }
+        };
+
+        let (mut facts, rules, privileged_rules, checks, policies) = verifier.dump();
+        // the world is a set, so its order changes from one run to the next
+        facts.sort_by_cached_key(|fact| fact.to_string());
+        biscuit_result.verifier_world_code = Some(
+            world_code(&facts, privileged_rules.iter().chain(rules.iter()), &checks, &policies),
+        );
+        biscuit_result.verifier_world = facts.drain(..).map(|fact| {
+            let origin = fact_origins.get(&fact.to_string()).cloned();
+            Fact { origin, ..Fact::from(fact) }
+        }).collect();
+        biscuit_result.world_total_facts = biscuit_result.verifier_world.len();
+        // rules from the authority block and the verifier come first, then
+        // the rules from the other blocks
+        biscuit_result.verifier_world_rules = privileged_rules
+            .iter()
+            .chain(rules.iter())
+            .map(|rule| rule.to_string())
+            .collect();
+        biscuit_result.verifier_world_checks = checks.iter().map(|check| check.to_string()).collect();
+
+        match &verifier_result {
+            Err(error::Token::FailedLogic(error::Logic::FailedChecks(v))) => {
+                for e in v.iter() {
+                    match e {
+                        error::FailedCheck::Verifier(error::FailedVerifierCheck {
+                            check_id, rule,
+                        }) => {
+                            verifier_block.checks[*check_id as usize].2 =
+                                Some(format!("verifier check failed: {}", rule));
+                        }
+                        error::FailedCheck::Block(error::FailedBlockCheck {
+                            block_id,
+                            check_id,
+                            rule,
+                        }) => {
+                            // loaded tokens have no source, so there may be no position to mark
+                            if let Some(check) = blocks
+                                .iter_mut()
+                                .filter(|b| b.enabled)
+                                .nth(*block_id as usize)
+                                .and_then(|b| b.checks.get_mut(*check_id as usize))
+                            {
+                                check.2 = Some(format!("check {} of block {} failed: {}", check_id, block_id, rule));
+                            }
+                        }
+                    }
+                }
+            },
+            Err(error::Token::FailedLogic(error::Logic::Deny(index))) => {
+                let position = &verifier_policies[*index].position;
+                if let Some(ed) = biscuit_result.verifier_editor.as_mut() {
+                    ed.markers.push(Marker::new("policy", false, position, MarkerSource::Verifier));
+                }
+                biscuit_result.matched_policy = Some(MatchedPolicy::new(&verifier_policies[*index]));
+            },
+            Ok(index) => {
+                let position = &verifier_policies[*index].position;
+                if let Some(ed) = biscuit_result.verifier_editor.as_mut() {
+                    ed.markers.push(Marker::new("policy", true, position, MarkerSource::Verifier));
+                }
+                biscuit_result.matched_policy = Some(MatchedPolicy::new(&verifier_policies[*index]));
+            },
+            // every allow policy failed to match
+            Err(error::Token::FailedLogic(error::Logic::NoMatchingPolicy)) => {
+                for policy in verifier_policies.iter().filter(|p| p.policy.kind == builder::PolicyKind::Allow) {
+                    if let Some(ed) = biscuit_result.verifier_editor.as_mut() {
+                        ed.markers.push(Marker::new("policy", false, &policy.position, MarkerSource::Verifier));
+                    }
+                }
+            },
+            _ => {},
+        }
+
+        // policies after the matched one, or all of them when the checks
+        // failed, still get a marker so they do not look like they were skipped
+        biscuit_result.policies = get_policy_evaluations(&verifier_result, &verifier_policies, &disabled);
+        for policy in biscuit_result.policies.iter() {
+            let kind = match policy.status.as_str() {
+                "not_reached" => "policy_not_reached",
+                "disabled" => "policy_disabled",
+                _ => continue,
+            };
+            if let Some(ed) = biscuit_result.verifier_editor.as_mut() {
+                ed.markers.push(Marker::new(kind, true, &policy.position, MarkerSource::Verifier));
+            }
+        }
+
+        for (id, block) in blocks.iter().enumerate() {
+            for (position, _, failure) in block.checks.iter() {
+                if let Some(ed) = biscuit_result.token_blocks.get_mut(id) {
+                    ed.markers.push(Marker::check(failure, position, MarkerSource::block(id)));
+                }
+            }
+        }
+
+        for (position, _, failure) in verifier_block.checks.iter() {
+            if let Some(ed) = biscuit_result.verifier_editor.as_mut() {
+                ed.markers.push(Marker::check(failure, position, MarkerSource::Verifier));
+            }
+        }
+
+        let block_checks = blocks.iter()
+            .enumerate()
+            .map(|(id, block)| (MarkerSource::block(id), block))
+            .chain(std::iter::once((MarkerSource::Verifier, &verifier_block)));
+        for (source, block) in block_checks {
+            biscuit_result.checks.extend(block.checks.iter().enumerate().map(|(index, (position, check, failure))| {
+                CheckResult {
+                    source,
+                    index,
+                    text: check_code(check),
+                    position: position.clone(),
+                    passed: failure.is_none(),
+                }
+            }));
+        }
+
+        // the world could not be generated, so the rules are not evaluated again
+        if !matches!(verifier_result, Err(error::Token::RunLimit(_))) {
+            for (id, block) in blocks.iter().enumerate() {
+                let source = MarkerSource::block(id);
+                biscuit_result.rule_derivations.extend(get_rule_derivations(&mut verifier, &block.rules, source, &limits));
+            }
+            biscuit_result.rule_derivations.extend(
+                get_rule_derivations(&mut verifier, &verifier_block.rules, MarkerSource::Verifier, &limits),
+            );
+
+            for derivation in biscuit_result.rule_derivations.iter() {
+                if derivation.error.is_some() || !derivation.facts.is_empty() {
+                    continue;
+                }
+
+                let ed = match derivation.source {
+                    MarkerSource::Authority => biscuit_result.token_blocks.get_mut(0),
+                    MarkerSource::Block(id) => biscuit_result.token_blocks.get_mut(id as usize),
+                    MarkerSource::Verifier => biscuit_result.verifier_editor.as_mut(),
+                };
+                if let Some(ed) = ed {
+                    ed.markers.push(Marker::new("unused_rule", false, &derivation.position, derivation.source));
+                }
+            }
+        }
+
+        biscuit_result.verifier_result = Some(VerifierResult::new(&verifier_result, &verifier_policies));
+        biscuit_result.verifier_errors = get_verifier_errors(&verifier_result, &verifier_policies);
+        // the blocks left out of the token are still in the editors
+        let editor_ids: Vec<usize> = blocks.iter()
+            .enumerate()
+            .filter(|(_, block)| block.enabled)
+            .map(|(id, _)| id)
+            .collect();
+        for error in biscuit_result.verifier_errors.iter_mut() {
+            error.editor_id = error.block_id
+                .and_then(|block_id| editor_ids.get(block_id as usize))
+                .map(|id| *id as u32);
+        }
+        for error in biscuit_result.verifier_errors.iter_mut().filter(|e| e.kind == "failed_check") {
+            explain_failed_check(&mut verifier, error, &limits);
+        }
+
+        Some(verifier)
+    }
+}
+
+// facts generated by each rule from the verifier's world. Querying the world
+// with the rule itself tells apart its facts from those of other rules with
+// the same head
+fn get_rule_derivations(
+    verifier: &mut Verifier,
+    rules: &[(SourcePosition, builder::Rule)],
+    source: MarkerSource,
+    limits: &VerifierLimits,
+) -> Vec<RuleDerivation> {
+    // the rules of the other blocks cannot generate facts for #authority or
+    // #ambient, the engine drops them
+    let restricted = !matches!(source, MarkerSource::Authority | MarkerSource::Verifier);
+
+    rules.iter().map(|(position, rule)| {
+        let facts: Result<Vec<builder::Fact>, String> = if rule.1.is_empty() {
+            Err(RULE_WITHOUT_PREDICATES.to_string())
+        } else {
+            verifier.query_with_limits(rule.clone(), limits.clone()).map_err(|e| format!("{:?}", e))
+        };
+        let (mut facts, error) = match facts {
+            Ok(facts) => (facts, None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
+        if restricted {
+            facts.retain(|fact| !matches!(
+                fact.0.ids.first(),
+                Some(builder::Term::Symbol(symbol)) if symbol == "authority" || symbol == "ambient"
+            ));
+        }
+        facts.sort_by_cached_key(|fact| fact.to_string());
+
+        RuleDerivation {
+            rule: rule.to_string(),
+            position: position.clone(),
+            source,
+            facts: facts.into_iter().map(Fact::from).collect(),
+            error,
+        }
+    }).collect()
+}
+
+// the single `query` fills `query_result`, while named queries each get
+// their own entry
+fn run_queries(
+    biscuit_result: &mut BiscuitResult,
+    verifier: &mut Verifier,
+    query: Option<&str>,
+    query_params: &HashMap<String, TermValue>,
+    queries: &[NamedQuery],
+    limits: VerifierLimits,
+) {
+    if let Some(query) = query {
+        info!("got query content: {}", query);
+
+        if !query.is_empty() {
+            // only the errors of the query's text get a marker, run limits
+            // are reported in `query_error` alone
+            let query_result: Result<Vec<builder::Fact>, String> = match parse_query(query, query_params) {
+                Err(e) => {
+                    biscuit_result.query_editor = Some(Editor {
+                        errors: vec![get_query_error(query, &e)],
+                        ..Default::default()
+                    });
+                    Err(e)
+                },
+                Ok(rule) => verifier.query_with_limits(rule, limits.clone()).map_err(|e| format!("{:?}", e)),
+            };
+            match query_result {
+                Err(e) => {
+                    error!("query error: {:?}", e);
+                    biscuit_result.query_error = Some(e);
+                },
+                Ok(mut facts) => {
+                    facts.sort_by_cached_key(|fact| fact.to_string());
+                    biscuit_result.query_result = facts.drain(..).map(Fact::from).collect();
+                }
+            }
+        }
+    }
+
+    for named in queries.iter() {
+        let mut result = NamedQueryResult { name: named.name.clone(), ..Default::default() };
+
+        let query_result: Result<Vec<builder::Fact>, String> = parse_query(&named.datalog, &named.params)
+            .and_then(|rule| verifier.query_with_limits(rule, limits.clone()).map_err(|e| format!("{:?}", e)));
+        match query_result {
+            Err(e) => {
+                result.error = Some(e);
+            },
+            Ok(mut facts) => {
+                facts.sort_by_cached_key(|fact| fact.to_string());
+                result.facts = facts.drain(..).map(Fact::from).collect();
+            }
+        }
+
+        biscuit_result.query_results.push(result);
+    }
+}
+
+/// error of `parse_query` for a rule without predicates
+const QUERY_WITHOUT_PREDICATES: &str = "a query needs at least one predicate in its body";
+
+/// error of the rules without predicates, refused in blocks and verifiers
+const RULE_WITHOUT_PREDICATES: &str = "a rule needs at least one predicate in its body";
+
+// parameters replace the variables of the parsed rule, so their values
+// never go through the parser
+fn parse_query(datalog: &str, params: &HashMap<String, TermValue>) -> Result<builder::Rule, String> {
+    let mut rule = builder::Rule::try_from(datalog).map_err(|e| format!("{:?}", e))?;
+    // the engine never returns from a query without predicates, whatever
+    // the limits
+    if rule.1.is_empty() {
+        return Err(QUERY_WITHOUT_PREDICATES.to_string());
+    }
+    if params.is_empty() {
+        return Ok(rule);
+    }
+
+    let mut values = HashMap::new();
+    for (name, value) in params.iter() {
+        let term = value.to_term().map_err(|e| format!("parameter \"{}\": {}", name, e))?;
+        values.insert(name.trim_start_matches('$').to_string(), term);
+    }
+
+    let substitute = |term: &mut builder::Term| {
+        if let builder::Term::Variable(name) = term {
+            if let Some(value) = values.get(name.as_str()) {
+                *term = value.clone();
+            }
+        }
+    };
+
+    let builder::Rule(head, body, expressions) = &mut rule;
+    head.ids.iter_mut().for_each(substitute);
+    body.iter_mut().flat_map(|predicate| predicate.ids.iter_mut()).for_each(substitute);
+    for expression in expressions.iter_mut() {
+        for op in expression.ops.iter_mut() {
+            if let builder::Op::Value(term) = op {
+                substitute(term);
+            }
+        }
+    }
+
+    Ok(rule)
+}
+
+// maps the facts declared in each block of the token to the index of the
+// first block declaring them
+fn get_fact_origins(token: &Biscuit) -> HashMap<String, u32> {
+    let mut origins = HashMap::new();
+
+    for index in 0..token.block_count() {
+        let source = token.print_block_source(index).unwrap_or_default();
+        if let Ok((_, parsed)) = parse_source(&source) {
+            for (_, fact) in parsed.facts.iter() {
+                origins.entry(fact.to_string()).or_insert(index as u32);
+            }
+        }
+    }
+
+    origins
+}
+
+// fills the fields describing the token itself
+fn set_token_info(biscuit_result: &mut BiscuitResult, token: &Biscuit, print_raw: bool) {
+    let blocks = get_token_blocks(token);
+
+    biscuit_result.token_content = token.print();
+    biscuit_result.block_count = token.block_count();
+    biscuit_result.is_attenuated = token.block_count() > 1;
+    biscuit_result.token_blocks_text = (0..token.block_count())
+        .map(|index| token.print_block_source(index).unwrap_or_default())
+        .collect();
+    biscuit_result.revocation_ids = token.revocation_identifiers().iter().map(hex::encode).collect();
+    biscuit_result.block_versions = blocks.iter().map(|block| block.version).collect();
+    biscuit_result.token_version = match biscuit_result.block_versions.split_first() {
+        Some((first, rest)) if rest.iter().all(|version| version == first) => Some(*first),
+        _ => None,
+    };
+    let symbols = get_token_symbols(&blocks);
+    biscuit_result.token_json = get_token_json(&blocks, &symbols);
+    biscuit_result.expires_at = get_expiration(&blocks, &symbols)
+        .and_then(|date| chrono::DateTime::from_timestamp(date as i64, 0))
+        .map(|date| date.to_rfc3339());
+    if print_raw {
+        biscuit_result.token_content_raw = Some(print_raw_blocks(&blocks, &symbols));
+    }
+    let default_symbols = default_symbol_table().symbols;
+    biscuit_result.custom_symbols = symbols.symbols.iter()
+        .filter(|symbol| !default_symbols.contains(symbol))
+        .cloned()
+        .collect();
+    biscuit_result.symbols = symbols.symbols;
+
+    biscuit_result.token_size = token.serialized_size().unwrap_or(0);
+    biscuit_result.token_fingerprint = token.to_vec().ok().map(|v| hex::encode(Sha256::digest(&v)));
+    if let Some(container) = token.container() {
+        biscuit_result.block_sizes = std::iter::once(&container.authority)
+            .chain(container.blocks.iter())
+            .map(|block| block.len())
+            .collect();
+        biscuit_result.block_keys = container.keys.iter().map(|key| hex::encode(key.to_bytes())).collect();
+    }
+}
+
+// biscuit-auth 1.2 has no dump_code, so the world is printed back here, one
+// statement per line
+fn world_code<'a>(
+    facts: &[builder::Fact],
+    rules: impl Iterator<Item = &'a builder::Rule>,
+    checks: &[builder::Check],
+    policies: &[builder::Policy],
+) -> String {
+    let mut code = String::new();
+    for fact in facts.iter() {
+        code.push_str(&format!("{};\n", predicate_code(&fact.0)));
+    }
+    for rule in rules {
+        code.push_str(&format!("{};\n", rule_code(rule)));
+    }
+    for check in checks.iter() {
+        code.push_str(&format!("{};\n", check_code(check)));
+    }
+    for policy in policies.iter() {
+        code.push_str(&format!("{};\n", policy_code(policy)));
+    }
+
+    code
+}
+
+fn rule_code(rule: &builder::Rule) -> String {
+    format!("{} <- {}", predicate_code(&rule.0), rule_body_code(rule))
+}
+
+fn check_code(check: &builder::Check) -> String {
+    format!("check if {}", queries_code(&check.queries))
+}
+
+fn policy_code(policy: &builder::Policy) -> String {
+    format!("{} if {}", get_policy_kind(policy), queries_code(&policy.queries))
+}
+
+fn queries_code(queries: &[builder::Rule]) -> String {
+    queries.iter().map(rule_body_code).collect::<Vec<_>>().join(" or ")
+}
+
+fn rule_body_code(rule: &builder::Rule) -> String {
+    rule.1
+        .iter()
+        .map(predicate_code)
+        .chain(rule.2.iter().map(expression_code))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn predicate_code(predicate: &builder::Predicate) -> String {
+    let terms = predicate.ids.iter().map(term_code).collect::<Vec<_>>();
+    format!("{}({})", predicate.name, terms.join(", "))
+}
+
+// printed from the operations like the Display implementation of
+// builder::Expression, with the values written by `term_code`
+fn expression_code(expression: &builder::Expression) -> String {
+    let symbols = default_symbol_table();
+    let mut stack: Vec<String> = Vec::new();
+    for op in expression.ops.iter() {
+        match op {
+            builder::Op::Value(term) => stack.push(term_code(term)),
+            builder::Op::Unary(unary) => match stack.pop() {
+                Some(value) => stack.push(unary.print(value, &symbols)),
+                None => return expression.to_string(),
+            },
+            builder::Op::Binary(binary) => match (stack.pop(), stack.pop()) {
+                (Some(right), Some(left)) => stack.push(binary.print(left, right, &symbols)),
+                _ => return expression.to_string(),
+            },
+        }
+    }
+
+    match stack.len() {
+        1 => stack.remove(0),
+        _ => expression.to_string(),
+    }
+}
+
+// the Display implementation of builder::Term prints dates as a SystemTime
+// and does not escape strings, which the parser does not accept
+fn term_code(term: &builder::Term) -> String {
+    match term {
+        builder::Term::Str(s) => {
+            format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+        },
+        builder::Term::Date(d) => chrono::DateTime::from_timestamp(*d as i64, 0)
+            .map(|date| date.to_rfc3339())
+            .unwrap_or_else(|| term.to_string()),
+        builder::Term::Set(set) => {
+            let terms = set.iter().map(term_code).collect::<Vec<_>>();
+            format!("[{}]", terms.join(", "))
+        }
+        _ => term.to_string(),
+    }
+}
+
+// earliest date bound on the `time` fact by the checks of the token
+fn get_expiration(blocks: &[TokenBlock], symbols: &SymbolTable) -> Option<u64> {
+    blocks.iter()
+        .flat_map(|block| block.checks.iter())
+        .filter_map(|check| {
+            let check = builder::Check::convert_from(check, symbols);
+            // the check passes if any query matches, so the time is only
+            // limited if every query limits it
+            check.queries.iter().map(query_expiration).collect::<Option<Vec<u64>>>()?.into_iter().max()
+        })
+        .min()
+}
+
+fn query_expiration(builder::Rule(_, body, expressions): &builder::Rule) -> Option<u64> {
+    let variables: Vec<&builder::Term> = body.iter()
+        .filter(|predicate| predicate.name == "time")
+        .filter_map(|predicate| predicate.ids.last())
+        .filter(|term| matches!(term, builder::Term::Variable(_)))
+        .collect();
+
+    expressions.iter().filter_map(|expression| {
+        match expression.ops.as_slice() {
+            [builder::Op::Value(variable), builder::Op::Value(builder::Term::Date(date)),
+             builder::Op::Binary(Binary::LessThan | Binary::LessOrEqual)]
+            | [builder::Op::Value(builder::Term::Date(date)), builder::Op::Value(variable),
+               builder::Op::Binary(Binary::GreaterThan | Binary::GreaterOrEqual)]
+                if variables.contains(&variable) => Some(*date),
+            _ => None,
+        }
+    }).min()
+}
+
+fn get_token_json(blocks: &[TokenBlock], symbols: &SymbolTable) -> Option<TokenJson> {
+    let mut blocks = blocks.iter().map(|block| {
+        let rule_json = |rule: builder::Rule| RuleJson {
+            source: rule.to_string(),
+            head: Predicate::from(rule.0),
+            body: rule.1.into_iter().map(Predicate::from).collect(),
+            expressions: rule.2.iter().map(expression_code).collect(),
+        };
+
+        BlockJson {
+            index: block.index,
+            context: block.context.clone(),
+            facts: block.facts.iter().map(|fact| Fact {
+                origin: Some(block.index),
+                ..Fact::from(builder::Fact::convert_from(fact, symbols))
+            }).collect(),
+            rules: block.rules.iter().map(|rule| rule_json(builder::Rule::convert_from(rule, symbols))).collect(),
+            checks: block.checks.iter().map(|check| {
+                let check = builder::Check::convert_from(check, symbols);
+                CheckJson {
+                    source: check.to_string(),
+                    queries: check.queries.into_iter().map(rule_json).collect(),
+                }
+            }).collect(),
+        }
+    }).collect::<Vec<_>>();
+
+    if blocks.is_empty() {
+        return None;
+    }
+    let authority = blocks.remove(0);
+
+    Some(TokenJson { authority, blocks })
+}
+
+// blocks only reference their own symbols and the ones of previous blocks,
+// so the whole table can decode any of them
+fn get_token_symbols(blocks: &[TokenBlock]) -> SymbolTable {
+    let mut symbols = default_symbol_table();
+    for block in blocks.iter() {
+        symbols.symbols.extend(block.symbols.symbols.iter().cloned());
+    }
+
+    symbols
+}
+
+// decodes the blocks of the token, authority first
+fn get_token_blocks(token: &Biscuit) -> Vec<TokenBlock> {
+    let container = match token.container() {
+        Some(container) => container,
+        None => return Vec::new(),
+    };
+
+    std::iter::once(&container.authority)
+        .chain(container.blocks.iter())
+        .filter_map(|bytes| schema::Block::decode(&bytes[..]).ok())
+        .filter_map(|block| proto_block_to_token_block(&block).ok())
+        .collect()
+}
+
+// prints the blocks with symbol indexes instead of the strings they stand
+// for, after the symbol table: `#4` is the symbol 4, `$5` the variable named
+// by the symbol 5 and `6(...)` a predicate named by the symbol 6
+fn print_raw_blocks(blocks: &[TokenBlock], symbols: &SymbolTable) -> String {
+    let indexes = SymbolTable {
+        symbols: (0..symbols.symbols.len()).map(|index| index.to_string()).collect(),
+    };
+
+    let mut content = String::from("symbols:\n");
+    for (index, symbol) in symbols.symbols.iter().enumerate() {
+        content.push_str(&format!("    {}: \"{}\"\n", index, symbol));
+    }
+    for block in blocks.iter() {
+        content.push_str(&format!("block {}:\n", block.index));
+        for fact in block.facts.iter() {
+            content.push_str(&format!("    {};\n", indexes.print_fact(fact)));
+        }
+        for rule in block.rules.iter() {
+            content.push_str(&format!("    {};\n", indexes.print_rule(rule)));
+        }
+        for check in block.checks.iter() {
+            content.push_str(&format!("    {};\n", indexes.print_check(check)));
+        }
+    }
+
+    content
+}
+
+// besides the logger and panic hook, the only global state is
+// `AUTHORITY_CACHE`. It is keyed by the authority block's source, so an
+// edited block misses the cache and the least recently used entry is
+// dropped. Every call still builds its tokens and verifier from scratch
+static INIT: Once = Once::new();
+
+#[wasm_bindgen(start)]
+pub fn run_app() {
+    // the logger can only be set once, so a second start would log an error
+    INIT.call_once(|| {
+        // the logger lets everything through, `set_log_level` filters
+        // with the global maximum level instead
+        wasm_logger::init(wasm_logger::Config::new(Level::Trace));
+        log::set_max_level(DEFAULT_LOG_LEVEL);
+        std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    });
+
+    log("wasm run_app");
+}
+
+/// the level of `wasm_logger::Config::default()`
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Debug;
+
+/// sets the level of the messages logged to the console, one of "off",
+/// "error", "warn", "info", "debug" or "trace". Returns false and keeps the
+/// current level if `level` is not one of them
+#[wasm_bindgen]
+pub fn set_log_level(level: &str) -> bool {
+    match level.trim().parse::<LevelFilter>() {
+        Ok(level) => {
+            log::set_max_level(level);
+            true
+        },
+        Err(_) => false,
+    }
+}
+
+// based on nom's convert_error
+fn get_position(input: &str, span: &str) -> SourcePosition {
+    let offset = input.offset(span);
+    let (line_start, column_start) = get_line_column(input, offset);
+    // the end can be the very end of the input, for a statement without a
+    // trailing `;` or newline
+    let (line_end, column_end) = get_line_column(input, (offset + span.len()).min(input.len()));
+
+    SourcePosition::new(line_start, column_start, line_end, column_end)
+}
+
+// line and column of a byte offset in the input
+fn get_line_column(input: &str, offset: usize) -> (usize, usize) {
+    let prefix = &input.as_bytes()[..offset];
+
+    // Count the number of newlines in the first `offset` bytes of input
+    let line = prefix.iter().filter(|&&b| b == b'\n').count();
+
+    // the line starts right after the last newline before the offset, or
+    // at the beginning of the input for the first line
+    let line_begin = prefix
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map(|pos| pos + 1)
+        .unwrap_or(0);
+
+    (line, offset - line_begin)
+}
+
+// a policy loaded in the verifier, with its location in the verifier code
+struct VerifierPolicy {
+    /// index of the policy in the verifier code, disabled ones included
+    pub index: usize,
+    pub position: SourcePosition,
+    pub source: String,
+    pub policy: builder::Policy,
+}
+
+/// a parsed block that does not borrow its source, the statements being
+/// located by their byte range in it
+#[derive(Clone)]
+struct ParsedBlock {
+    pub facts: Vec<(Range<usize>, builder::Fact)>,
+    pub rules: Vec<(Range<usize>, builder::Rule)>,
+    pub checks: Vec<(Range<usize>, builder::Check)>,
+}
+
+/// number of authority blocks kept parsed between calls
+const AUTHORITY_CACHE_SIZE: usize = 4;
+
+type ParsedAuthority = Result<ParsedBlock, Vec<ParseError>>;
+
+thread_local! {
+    // most recently used first. The editor calls `execute` on every change,
+    // and most of the time the authority block did not change
+    static AUTHORITY_CACHE: RefCell<Vec<(String, ParsedAuthority)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn parse_authority(code: &str) -> ParsedAuthority {
+    AUTHORITY_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+
+        let parsed = match cache.iter().position(|(source, _)| source == code) {
+            Some(index) => cache.remove(index).1,
+            None => {
+                let span = |i: &str| code.offset(i)..code.offset(i) + i.len();
+                parse_datalog(code)
+                    .map(|(_, parsed)| ParsedBlock {
+                        facts: parsed.facts.into_iter().map(|(i, fact)| (span(i), fact)).collect(),
+                        rules: parsed.rules.into_iter().map(|(i, rule)| (span(i), rule)).collect(),
+                        checks: parsed.checks.into_iter().map(|(i, check)| (span(i), check)).collect(),
+                    })
+                    .map_err(|errors| get_parse_errors(code, errors))
+            }
+        };
+
+        cache.insert(0, (code.to_string(), parsed.clone()));
+        cache.truncate(AUTHORITY_CACHE_SIZE);
+        parsed
+    })
+}
+
+/// positions of the elements of a block that were added to the token or verifier
+#[derive(Clone, Debug)]
+struct Block {
+    pub facts: Vec<SourcePosition>,
+    pub rules: Vec<(SourcePosition, builder::Rule)>,
+    /// with the failure message of the check, set after verification
+    pub checks: Vec<(SourcePosition, builder::Check, Option<String>)>,
+    /// false if the block was left out of the token, so indexes in the token
+    /// skip it
+    pub enabled: bool,
+}
+
+impl Default for Block {
+    fn default() -> Self {
+        Block {
+            facts: Vec::new(),
+            rules: Vec::new(),
+            checks: Vec::new(),
+            enabled: true,
+        }
+    }
+}
+
+impl Block {
+    fn elements(&self) -> Vec<Element> {
+        let element = |kind: &str, position: &SourcePosition| Element {
+            kind: kind.to_string(),
+            position: position.clone(),
+        };
+
+        self.facts.iter().map(|position| element("fact", position))
+            .chain(self.rules.iter().map(|(position, _)| element("rule", position)))
+            .chain(self.checks.iter().map(|(position, _, _)| element("check", position)))
+            .collect()
+    }
+}
+
+fn get_parse_errors(input: &str, errors: Vec<biscuit_auth::parser::Error>) -> Vec<ParseError> {
+    let mut res = Vec::new();
+
+    error!("got errors: {:?}", errors);
+    for e in errors.iter() {
+        let recoverable = Recoverable::new(input, e);
+        let span = recoverable.map(|recoverable| recoverable.span(input, e)).unwrap_or(e.input);
+        let position = get_position(input, span);
+        let component = get_component(input, e.input).to_string();
+
+        let message = if let Some(recoverable) = recoverable {
+            recoverable.message().to_string()
+        } else if get_statement(input, e.input).starts_with("reject") {
+            "`reject if` is not supported by this version of biscuit, only `check if`".to_string()
+        } else {
+            e.message.as_ref().cloned().unwrap_or_else(|| format!("error: {:?}", e.code))
+        };
+        let severity = if recoverable.is_some() { Severity::Warning } else { Severity::Error };
+
+        error!("position for error({:?}) \"{}\": {:?}", e.code, message, position);
+        let code = ErrorCode::new(input, e);
+        let snippet = get_snippet(span);
+        res.push(ParseError { message, position, component, severity, code, snippet });
+    }
+
+    res
+}
+
+// the rule parser only reports that the query is invalid, so the error
+// covers the whole query
+fn get_query_error(query: &str, message: &str) -> ParseError {
+    let code = if message == QUERY_WITHOUT_PREDICATES {
+        ErrorCode::QueryWithoutPredicates
+    } else {
+        ErrorCode::UnexpectedToken
+    };
+
+    ParseError {
+        message: message.to_string(),
+        position: get_position(query, query),
+        component: "rule".to_string(),
+        severity: Severity::Error,
+        code,
+        snippet: get_snippet(query),
+    }
+}
+
+// the engine never returns once a rule without predicates is loaded, so
+// the rule is left out like the elements refused by the builders
+fn get_rule_error(input: &str, span: &str) -> ParseError {
+    ParseError {
+        message: RULE_WITHOUT_PREDICATES.to_string(),
+        position: get_position(input, span),
+        component: "rule".to_string(),
+        severity: Severity::Error,
+        code: ErrorCode::RuleWithoutPredicates,
+        snippet: get_snippet(span),
+    }
+}
+
+/// syntax errors that leave every statement of the source complete
+#[derive(Clone, Copy)]
+enum Recoverable {
+    /// a `;` right after another one or at the start of the input
+    EmptyStatement,
+    /// a `/*` without `*/`, the rest of the input being the comment
+    UnclosedComment,
+}
+
+impl Recoverable {
+    fn new(input: &str, e: &biscuit_auth::parser::Error) -> Option<Self> {
+        let offset = input.offset(e.input);
+        let before = input[..offset].trim_end();
+
+        if e.code == nom::error::ErrorKind::Tag
+            && input[offset..].starts_with(';')
+            && (before.is_empty() || before.ends_with(';'))
+        {
+            Some(Recoverable::EmptyStatement)
+        } else if e.code == nom::error::ErrorKind::TakeUntil && before.ends_with("/*") {
+            Some(Recoverable::UnclosedComment)
+        } else {
+            None
+        }
+    }
+
+    // the `;`, or the comment up to the end of the input
+    fn span<'a>(self, input: &'a str, e: &biscuit_auth::parser::Error) -> &'a str {
+        let offset = input.offset(e.input);
+        match self {
+            Recoverable::EmptyStatement => &input[offset..offset + 1],
+            Recoverable::UnclosedComment => &input[input[..offset].rfind("/*").unwrap_or(offset)..],
+        }
+    }
+
+    fn code(self) -> ErrorCode {
+        match self {
+            Recoverable::EmptyStatement => ErrorCode::EmptyStatement,
+            Recoverable::UnclosedComment => ErrorCode::UnclosedComment,
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            Recoverable::EmptyStatement => "this `;` ends no statement and is ignored",
+            Recoverable::UnclosedComment => "this comment is not closed, the rest of the input is ignored",
+        }
+    }
+}
+
+// parses the source like `parse_source`, but if every error is recoverable
+// the statements are parsed again without the empty statements and with the
+// comment closed. The spans still point into `input`
+fn parse_datalog(input: &str) -> Result<(&str, SourceResult<'_>), Vec<biscuit_auth::parser::Error<'_>>> {
+    let errors = match parse_source(input) {
+        Err(errors) => errors,
+        parsed => return parsed,
+    };
+
+    let mut repaired = input.to_string();
+    for e in errors.iter() {
+        let offset = input.offset(e.input);
+        match Recoverable::new(input, e) {
+            Some(Recoverable::EmptyStatement) => repaired.replace_range(offset..offset + 1, " "),
+            Some(Recoverable::UnclosedComment) => repaired.push_str("*/"),
+            None => return Err(errors),
+        }
+    }
+
+    // the repair keeps the length of the input, only adding after its end
+    let span = |i: &str| {
+        let start = repaired.offset(i).min(input.len());
+        &input[start..(start + i.len()).min(input.len())]
+    };
+    match parse_source(&repaired) {
+        Ok((_, parsed)) => Ok((&input[input.len()..], SourceResult {
+            facts: parsed.facts.into_iter().map(|(i, fact)| (span(i), fact)).collect(),
+            rules: parsed.rules.into_iter().map(|(i, rule)| (span(i), rule)).collect(),
+            checks: parsed.checks.into_iter().map(|(i, check)| (span(i), check)).collect(),
+            policies: parsed.policies.into_iter().map(|(i, policy)| (span(i), policy)).collect(),
+        })),
+        Err(_) => Err(errors),
+    }
+}
+
+// the errors `parse_datalog` recovered from, as warnings
+fn get_recovered_errors(input: &str) -> Vec<ParseError> {
+    match parse_source(input) {
+        Err(errors) if parse_datalog(input).is_ok() => get_parse_errors(input, errors),
+        _ => Vec::new(),
+    }
+}
+
+// top level error of `fail_fast`
+fn parse_failure(block_index: usize) -> String {
+    format!("token block {} could not be parsed", block_index)
+}
+
+// errors reported by the builders on elements that were parsed correctly
+fn get_token_error(input: &str, span: &str, e: error::Token) -> ParseError {
+    let position = get_position(input, span);
+    let message = format!("error: {:?}", e);
+
+    let component = get_component(input, span).to_string();
+
+    error!("position for error \"{}\": {:?}", message, position);
+    ParseError {
+        message,
+        position,
+        component,
+        severity: Severity::Error,
+        code: ErrorCode::InvalidElement,
+        snippet: get_snippet(span),
+    }
+}
+
+// guesses which kind of element contains the span, from the leading keyword
+// of the statement around it (statements are separated by `;`)
+fn get_component(input: &str, span: &str) -> &'static str {
+    let statement = get_statement(input, span);
+
+    // `reject if` is not part of the grammar of biscuit-auth 1.x, but it is
+    // still the check the user meant to write
+    if statement.starts_with("check") || statement.starts_with("reject") {
+        "check"
+    } else if statement.starts_with("allow") || statement.starts_with("deny") {
+        "policy"
+    } else if statement.contains("<-") {
+        "rule"
+    } else {
+        "fact"
+    }
+}
+
+// the statement around the span, without comments and with its lines joined
+fn get_statement(input: &str, span: &str) -> String {
+    let offset = input.offset(span);
+    let start = input[..offset].rfind(';').map(|i| i + 1).unwrap_or(0);
+    let end = input[offset..].find(';').map(|i| offset + i).unwrap_or_else(|| input.len());
+
+    input[start..end]
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with("//"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(token_blocks: &[&str], verifier_code: &str) -> BiscuitQuery {
+        BiscuitQuery {
+            token_blocks: token_blocks.iter().map(|block| block.to_string()).collect(),
+            verifier_code: Some(verifier_code.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn query_without_predicates_is_rejected() {
+        for datalog in ["q(1) <- true", "q(1) <- 1 == 1"] {
+            let result = execute_inner(BiscuitQuery {
+                query: Some(datalog.to_string()),
+                ..query(&["a(1);"], "allow if true;")
+            });
+
+            assert_eq!(result.query_error.as_deref(), Some(QUERY_WITHOUT_PREDICATES));
+            let errors = &result.query_editor.unwrap().errors;
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].code == ErrorCode::QueryWithoutPredicates);
+        }
+    }
+
+    #[test]
+    fn query_world_matches_full_run() {
+        let datalog = "user($u) <- right(#authority, $u, #read)";
+        let full = execute_inner(BiscuitQuery {
+            query: Some(datalog.to_string()),
+            ..query(&["right(#authority, \"alice\", #read);\nright(#authority, \"bob\", #write);"], "allow if true;")
+        });
+        assert_eq!(full.query_result.len(), 1);
+
+        let world_code = full.verifier_world_code.unwrap();
+        let cached = query_world_inner(&world_code, datalog);
+        assert!(cached.error.is_none());
+        let codes = |facts: &[Fact]| facts.iter().map(|fact| fact.code.clone()).collect::<Vec<_>>();
+        assert_eq!(codes(&cached.facts), codes(&full.query_result));
+    }
+
+    #[test]
+    fn query_world_rejects_query_without_predicates() {
+        let result = query_world_inner("a(1);", "q(1) <- 1 == 1");
+        assert_eq!(result.error.as_deref(), Some(QUERY_WITHOUT_PREDICATES));
+    }
+
+    #[test]
+    fn verifier_blocks_ending_in_comments() {
+        let result = execute_inner(BiscuitQuery {
+            verifier_blocks: Some(vec![
+                "check if a(1) // \"no ; here\"".to_string(),
+                "allow if true; // note".to_string(),
+                "deny if true /* last */".to_string(),
+            ]),
+            ..query(&["a(1);"], "")
+        });
+
+        assert_eq!(result.verifier_code.as_deref(), Some(
+            "check if a(1); // \"no ; here\"\nallow if true; // note\ndeny if true; /* last */\n"
+        ));
+        assert!(result.error.is_none(), "{:?}", result.error);
+        assert_eq!(result.verifier_editors.len(), 3);
+        assert!(result.verifier_editors.iter().all(|editor| editor.errors.is_empty()));
+    }
+
+    #[test]
+    fn verifier_block_errors_stay_in_their_snippet() {
+        let result = execute_inner(BiscuitQuery {
+            verifier_blocks: Some(vec![
+                "allow if true; // note".to_string(),
+                "deny if true; // note\ncheck if a(".to_string(),
+            ]),
+            ..query(&["a(1);"], "")
+        });
+
+        assert!(result.verifier_editors[0].errors.is_empty());
+        let errors = &result.verifier_editors[1].errors;
+        assert!(!errors.is_empty());
+        assert_eq!(errors[0].position.line_start, 1);
+        assert_eq!(errors[0].position.column_start, "check if a(".len());
+    }
+
+    #[test]
+    fn disabled_policy_before_the_matching_one() {
+        let result = execute_inner(BiscuitQuery {
+            disabled_policies: Some(vec![0]),
+            ..query(&["a(1);"], "deny if true;\nallow if a(1);\ndeny if true;")
+        });
+
+        let status = &result.verifier_result.unwrap().status;
+        assert!(matches!(status, VerifierStatus::Success { policy: 1 }));
+        assert_eq!(result.matched_policy.unwrap().index, 1);
+        let statuses = result.policies.iter()
+            .map(|policy| (policy.index, policy.status.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(statuses, [(0, "disabled"), (1, "matched"), (2, "not_reached")]);
+    }
+
+    #[test]
+    fn set_expressions_in_checks() {
+        let result = execute_inner(query(&[
+            "right(#read);\n\
+             check if right($r), [#read, #write].contains($r);\n\
+             check if right($r), [#write].contains($r);",
+        ], "allow if true;"));
+
+        let failed = result.verifier_errors.iter()
+            .map(|error| (error.block_id, error.check_id))
+            .collect::<Vec<_>>();
+        assert_eq!(failed, [(Some(0), Some(1))]);
+        let passed = result.checks.iter().map(|check| (check.index, check.passed)).collect::<Vec<_>>();
+        assert_eq!(passed, [(0, true), (1, false)]);
+    }
+
+    // the 1.x parser has no syntax for union and intersection, so the
+    // checks are built directly
+    #[test]
+    fn set_union_and_intersection_in_token_checks() {
+        let set = |values: &[i64]| builder::set(values.iter().map(|value| builder::int(*value)).collect());
+        let check = |operation: Binary| builder::Check {
+            queries: vec![builder::constrained_rule(
+                "query",
+                &[builder::var("x")],
+                &[builder::pred("a", &[builder::var("x")])],
+                &[builder::Expression { ops: vec![
+                    builder::Op::Value(set(&[1, 2])),
+                    builder::Op::Value(set(&[2, 3])),
+                    builder::Op::Binary(operation),
+                    builder::Op::Value(builder::var("x")),
+                    builder::Op::Binary(Binary::Contains),
+                ] }],
+            )],
+        };
+
+        let root = KeyPair::new_with_rng(&mut StdRng::seed_from_u64(0));
+        let mut builder = Biscuit::builder(&root);
+        builder.add_authority_fact("a(3)").unwrap();
+        builder.add_authority_check(check(Binary::Union)).unwrap();
+        builder.add_authority_check(check(Binary::Intersection)).unwrap();
+        let token = builder.build().unwrap();
+
+        let result = verify_token_inner(Ok(token), &[hex::encode(root.public().to_bytes())], "allow if true;");
+        assert_eq!(result.verifier_errors.len(), 1);
+        let error = &result.verifier_errors[0];
+        assert_eq!((error.block_id, error.check_id), (Some(0), Some(1)));
+        assert!(error.rule.as_deref().unwrap().contains("[1, 2].intersection([2, 3]).contains($x)"));
+    }
+
+    #[test]
+    fn set_expressions_in_policies() {
+        let result = execute_inner(query(&["right(#read);"], "\
+            allow if right($r), [#admin].contains($r);\n\
+            allow if right($r), [#read, #write].contains($r);"));
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::Success { policy: 1 }));
+
+        let result = execute_inner(query(&["right(#read);"], "\
+            allow if right($r), [#admin].contains($r);\n\
+            deny if right($r), [#read].contains($r);"));
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::Deny { policy: 1 }));
+
+        let result = execute_inner(query(&["right(#read);"], "\
+            allow if right($r), [#admin].contains($r);\n\
+            allow if right($r), [#write].contains($r);"));
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::NoMatchingPolicy));
+        let markers = result.verifier_editor.unwrap().markers;
+        let policies = markers.iter().filter(|marker| marker.kind == "policy").collect::<Vec<_>>();
+        assert_eq!(policies.len(), 2);
+        assert!(policies.iter().all(|marker| !marker.ok));
+    }
+
+    #[test]
+    fn set_terms_round_trip() {
+        let result = execute_inner(query(&["s([\"a\", \"b\\\"c\"]);"], "allow if true;"));
+        let fact = result.verifier_world.iter().find(|fact| fact.name == "s").unwrap();
+
+        match &fact.terms[..] {
+            [Term::Set(set)] => assert_eq!(set.len(), 2),
+            _ => panic!("expected a set"),
+        }
+        let reparsed = builder::Fact::try_from(fact.code.as_str()).unwrap();
+        assert_eq!(reparsed, builder::Fact::try_from("s([\"a\", \"b\\\"c\"])").unwrap());
+        assert_eq!(fact.code, format!("s({})", fact.term_codes[0]));
+    }
+
+    #[test]
+    fn repeated_calls_are_independent() {
+        let mut first_results: HashMap<usize, Vec<String>> = HashMap::new();
+        for call in 0..1000usize {
+            let count = call % 10;
+            let facts = (0..=count).map(|i| format!("a({});", i)).collect::<Vec<_>>().join("\n");
+            let result = execute_inner(query(&[&format!("{}\nb($x) <- a($x), $x > 2;", facts)], "allow if b($x);"));
+
+            let status = &result.verifier_result.as_ref().unwrap().status;
+            assert_eq!(matches!(status, VerifierStatus::Success { policy: 0 }), count > 2);
+            // `time` changes between calls, and the revocation ids with the facts
+            let world = result.verifier_world.iter()
+                .filter(|fact| fact.name == "a" || fact.name == "b")
+                .map(|fact| fact.code.clone())
+                .collect::<Vec<_>>();
+            assert_eq!(world.len(), (count + 1) + count.saturating_sub(2));
+            assert_eq!(first_results.entry(count).or_insert_with(|| world.clone()), &world);
+        }
+    }
+
+    #[test]
+    fn no_time_limit_with_capped_iterations() {
+        // the rule needs one iteration per edge to find every path
+        let edges = (0..30).map(|i| format!("edge({}, {});", i, i + 1)).collect::<Vec<_>>().join("\n");
+        let program = format!("{}\npath($x, $y) <- edge($x, $y);\npath($x, $z) <- edge($x, $y), path($y, $z);", edges);
+        let run = |max_time_millis: u64, max_iterations: u32| execute_inner(BiscuitQuery {
+            verifier_limits: Some(VerifierLimitsInput {
+                max_facts: Some(100_000),
+                max_iterations: Some(max_iterations),
+                max_time_millis: Some(max_time_millis),
+            }),
+            ..query(&[&program], "allow if path(0, 30);")
+        });
+
+        let result = run(1, 1_000);
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::Timeout));
+
+        let result = run(0, 1_000);
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::Success { policy: 0 }));
+
+        let result = run(0, 10);
+        let kinds = result.verifier_errors.iter().map(|error| error.kind.as_str()).collect::<Vec<_>>();
+        assert_eq!(kinds, ["too_many_iterations"]);
+    }
+
+    fn position(position: &SourcePosition) -> (usize, usize, usize, usize) {
+        (position.line_start, position.column_start, position.line_end, position.column_end)
+    }
+
+    #[test]
+    fn positions_on_the_first_line() {
+        let input = "right(#read);\ncheck if right(#write);";
+
+        assert_eq!(position(&get_position(input, &input[..5])), (0, 0, 0, 5));
+        assert_eq!(position(&get_position(input, &input[6..11])), (0, 6, 0, 11));
+        assert_eq!(position(&get_position(input, &input[6..19])), (0, 6, 1, 5));
+
+        let result = execute_inner(query(&["#read;"], "allow if true;"));
+        let start = &result.token_blocks[0].errors[0].position;
+        assert_eq!((start.line_start, start.column_start), (0, 0));
+    }
+
+    #[test]
+    fn positions_at_the_end_of_the_input() {
+        let input = "a(1);\ncheck if a(1)";
+        assert_eq!(position(&get_position(input, &input[input.len()..])), (1, 13, 1, 13));
+
+        for (block, kind) in [("a(1);\ncheck if a(1)", "check"), ("check if a(1);\na(1)", "fact")] {
+            let result = execute_inner(query(&[block], "allow if true;"));
+            let editor = &result.token_blocks[0];
+            assert!(editor.errors.is_empty());
+            let last = editor.elements.iter().max_by_key(|element| element.position.line_start).unwrap();
+            assert_eq!(last.kind, kind);
+            assert_eq!(position(&last.position), (1, 0, 1, block.lines().last().unwrap().len()));
+        }
+    }
+
+    // biscuit-auth 1.x has no `reject if`: the block is refused, and the
+    // checks of the other blocks keep their check_id
+    #[test]
+    fn reject_if_is_reported() {
+        let result = execute_inner(query(&[
+            "a(1);\ncheck if a(1);\ncheck if a(2);",
+            "check if a(1);\nreject if a(1);\ncheck if a(3);",
+        ], "allow if true;"));
+
+        let errors = &result.token_blocks[1].errors;
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].code == ErrorCode::UnsupportedReject);
+        assert_eq!(errors[0].message, "`reject if` is not supported by this version of biscuit, only `check if`");
+        assert_eq!(errors[0].component, "check");
+        assert_eq!(position(&errors[0].position), (1, 0, 1, 14));
+
+        let failed = result.verifier_errors.iter()
+            .map(|error| (error.block_id, error.check_id, error.rule.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(failed, [(Some(0), Some(1), Some("check if a(2)"))]);
+        let markers = result.token_blocks[0].markers.iter().map(|marker| marker.ok).collect::<Vec<_>>();
+        assert_eq!(markers, [true, false]);
+    }
+
+    #[test]
+    fn public_key_parsing() {
+        let error = |key: &str| parse_public_key(key).err().unwrap();
+        assert_eq!(error(""), "expected a 32 bytes hex encoded public key, got 0 bytes");
+        assert_eq!(error("abcd"), "expected a 32 bytes hex encoded public key, got 2 bytes");
+        assert!(error("not hex").starts_with("expected a hex encoded public key"));
+
+        let keypair = generate_keypair_inner(Some(0));
+        let key = parse_public_key(&format!(" {}\n", keypair.public_key)).unwrap();
+        assert_eq!(hex::encode(key.to_bytes()), keypair.public_key);
+
+        let token = Biscuit::builder(&KeyPair::new_with_rng(&mut StdRng::seed_from_u64(0))).build();
+        let result = verify_token_inner(token, &["abcd".to_string()], "allow if true;");
+        assert_eq!(
+            result.error.as_deref(),
+            Some("invalid root public key at index 0: expected a 32 bytes hex encoded public key, got 2 bytes"),
+        );
+    }
+
+    #[test]
+    fn blocks_with_one_kind_of_element() {
+        let result = execute_inner(query(&[
+            "a(1);\na(2);",
+            "b($x) <- a($x);",
+            "check if b(1);\ncheck if b(3);",
+            "// nothing here\n/* still nothing */",
+            " \n",
+            "check if c(1);",
+        ], "allow if true;"));
+
+        assert!(result.token_blocks.iter().all(|editor| editor.errors.is_empty()));
+        let world = result.verifier_world.iter().map(|fact| fact.code.as_str()).collect::<Vec<_>>();
+        assert!(world.contains(&"b(1)") && world.contains(&"b(2)"));
+
+        // the commented block is appended empty, while the blank one is left
+        // out of the token but keeps its editor
+        assert_eq!(result.block_count, 5);
+        let failed = result.verifier_errors.iter()
+            .map(|error| (error.block_id, error.check_id, error.editor_id))
+            .collect::<Vec<_>>();
+        assert_eq!(failed, [(Some(2), Some(1), Some(2)), (Some(4), Some(0), Some(5))]);
+        let markers = |index: usize| result.token_blocks[index].markers.iter().map(|marker| marker.ok).collect::<Vec<_>>();
+        assert_eq!(markers(2), [true, false]);
+        assert!(markers(3).is_empty() && markers(4).is_empty());
+        assert_eq!(markers(5), [false]);
+    }
+
+    #[test]
+    fn set_terms_keep_their_elements() {
+        let result = execute_inner(BiscuitQuery {
+            query: Some("t($s) <- s($s)".to_string()),
+            ..query(&["s([3, 1, 2]);"], "allow if s($s), $s.contains(2);")
+        });
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::Success { policy: 0 }));
+
+        let world_fact = result.verifier_world.iter().find(|fact| fact.name == "s").unwrap();
+        for fact in [world_fact, &result.query_result[0]] {
+            let elements = match &fact.terms[..] {
+                [Term::Set(set)] => set.iter().map(|term| match term {
+                    Term::Int(i) => *i,
+                    _ => panic!("expected integers"),
+                }).collect::<Vec<_>>(),
+                _ => panic!("expected a set"),
+            };
+            assert_eq!(elements, [1, 2, 3]);
+            assert_eq!(fact.term_codes, ["[1, 2, 3]"]);
+        }
+    }
+
+    fn cached_authorities() -> Vec<String> {
+        AUTHORITY_CACHE.with(|cache| cache.borrow().iter().map(|(source, _)| source.clone()).collect())
+    }
+
+    #[test]
+    fn authority_cache() {
+        AUTHORITY_CACHE.with(|cache| cache.borrow_mut().clear());
+
+        // a hit moves the block to the front without parsing it again
+        assert!(parse_authority("a(1);").is_ok());
+        assert!(parse_authority("a(2);").is_ok());
+        assert_eq!(cached_authorities(), ["a(2);", "a(1);"]);
+        assert_eq!(parse_authority("a(1);").ok().unwrap().facts.len(), 1);
+        assert_eq!(cached_authorities(), ["a(1);", "a(2);"]);
+
+        // parse errors are cached too
+        let errors = |code: &str| parse_authority(code).err().unwrap().len();
+        assert_eq!(errors("a("), errors("a("));
+        assert_eq!(cached_authorities().len(), 3);
+
+        for code in ["b(1);", "b(2);"] {
+            assert!(parse_authority(code).is_ok());
+        }
+        assert_eq!(cached_authorities(), ["b(2);", "b(1);", "a(", "a(1);"]);
+
+        // a changed authority block is parsed again
+        let world = |authority: &str| {
+            let result = execute_inner(query(&[authority], "allow if true;"));
+            result.verifier_world.iter().filter(|fact| fact.name == "a").map(|fact| fact.code.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(world("a(1);"), ["a(1)"]);
+        assert_eq!(world("a(2);\na(3);"), ["a(2)", "a(3)"]);
+        assert_eq!(world("a(1);"), ["a(1)"]);
+        assert_eq!(cached_authorities()[..2], ["a(1);", "a(2);\na(3);"]);
+    }
+
+    #[test]
+    fn rendered_terms_parse_back() {
+        let source = r#"f("a \"b\" \\ c", -1, true, 2021-01-01T00:00:00Z, hex:0aff, #sym, [#x, #y])"#;
+        let result = execute_inner(query(&[&format!("{};", source)], "allow if true;"));
+        assert!(result.token_blocks[0].errors.is_empty());
+
+        let fact = result.verifier_world.iter().find(|fact| fact.name == "f").unwrap();
+        let expected = builder::Fact::try_from(source).unwrap();
+        assert_eq!(builder::Fact::try_from(fact.code.as_str()).unwrap(), expected);
+        let from_terms = format!("f({})", fact.term_codes.join(", "));
+        assert_eq!(builder::Fact::try_from(from_terms.as_str()).unwrap(), expected);
+    }
+
+    #[test]
+    fn rule_derivations_follow_the_block_restrictions() {
+        let result = execute_inner(query(&[
+            "tag(#authority);\ntag(#other);\nc($t) <- tag($t);",
+            "d($t) <- tag($t);",
+        ], "tag(#ambient);\ne($t) <- tag($t);\nallow if true;"));
+        assert!(result.error.is_none());
+
+        let derivations = result.rule_derivations.iter()
+            .map(|derivation| (derivation.rule.as_str(), derivation.facts.iter().map(|fact| fact.code.as_str()).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(derivations, [
+            ("c($t) <- tag($t)", vec!["c(#ambient)", "c(#authority)", "c(#other)"]),
+            ("d($t) <- tag($t)", vec!["d(#other)"]),
+            ("e($t) <- tag($t)", vec!["e(#ambient)", "e(#authority)", "e(#other)"]),
+        ]);
+        let world = result.verifier_world.iter().filter(|fact| fact.name == "d").count();
+        assert_eq!(world, 1);
+    }
+
+    fn one_based(position: &SourcePosition) -> (usize, usize, usize, usize) {
+        let position = &position.one_based;
+        (position.line_start, position.column_start, position.line_end, position.column_end)
+    }
+
+    #[test]
+    fn one_based_positions() {
+        let input = "right(#read);\ncheck if right(#write)";
+
+        // first line, across lines and at the end of the input
+        assert_eq!(one_based(&get_position(input, &input[..5])), (1, 1, 1, 6));
+        assert_eq!(one_based(&get_position(input, &input[6..19])), (1, 7, 2, 6));
+        assert_eq!(one_based(&get_position(input, &input[14..])), (2, 1, 2, 23));
+        assert_eq!(one_based(&get_position(input, &input[input.len()..])), (2, 23, 2, 23));
+
+        // snippets get both conventions shifted to their own lines
+        let result = execute_inner(BiscuitQuery {
+            verifier_blocks: Some(vec!["allow if true;".to_string(), "a(1);\ncheck if a(2);".to_string()]),
+            ..query(&["a(1);"], "")
+        });
+        let check = result.verifier_editors[1].elements.iter().find(|element| element.kind == "check").unwrap();
+        assert_eq!(position(&check.position), (1, 0, 1, 13));
+        assert_eq!(one_based(&check.position), (2, 1, 2, 14));
+    }
+
+    #[test]
+    fn recoverable_parse_errors_are_warnings() {
+        let result = execute_inner(query(&["a(1);;\nb(1);\n/* todo"], ";\nallow if a(1), b(1);"));
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::Success { policy: 0 }));
+
+        let editor = &result.token_blocks[0];
+        assert!(editor.errors.is_empty());
+        let warnings = editor.warnings.iter()
+            .map(|warning| (warning.code, position(&warning.position), warning.snippet.as_str()))
+            .collect::<Vec<_>>();
+        assert!(warnings == [
+            (ErrorCode::EmptyStatement, (0, 5, 0, 6), ";"),
+            (ErrorCode::UnclosedComment, (2, 0, 2, 7), "/* todo"),
+        ]);
+        assert!(editor.warnings.iter().all(|warning| matches!(warning.severity, Severity::Warning)));
+        let verifier_editor = result.verifier_editor.unwrap();
+        assert!(verifier_editor.errors.is_empty());
+        assert!(verifier_editor.warnings[0].code == ErrorCode::EmptyStatement);
+
+        let editors = lint_inner(&["a(1);;".to_string()]);
+        assert!(editors[0].errors.is_empty());
+        assert!(editors[0].warnings[0].code == ErrorCode::EmptyStatement);
+    }
+
+    #[test]
+    fn recoverable_parse_errors_next_to_hard_ones() {
+        let result = execute_inner(query(&["a(1);;\nb(", "check if ;"], "allow if true;"));
+
+        let errors = &result.token_blocks[0].errors;
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].code == ErrorCode::EmptyStatement && matches!(errors[0].severity, Severity::Warning));
+        assert!(matches!(errors[1].severity, Severity::Error));
+
+        // a `;` ending an incomplete statement is not an empty statement
+        let errors = &result.token_blocks[1].errors;
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].code != ErrorCode::EmptyStatement && matches!(errors[0].severity, Severity::Error));
+    }
+
+    #[test]
+    fn private_keys_sign_with_system_randomness() {
+        let keypair = generate_keypair_inner(Some(0));
+        let signed = |seed| execute_inner(BiscuitQuery {
+            root_private_key: Some(keypair.private_key.clone()),
+            root_seed: seed,
+            deterministic: Some(true),
+            block_seed: Some(1),
+            ..query(&["a(1);", "check if a(1);"], "allow if true;")
+        });
+
+        let first = signed(Some(0));
+        let second = signed(Some(0));
+        assert!(first.serialized.is_some());
+        assert!(first.serialized != second.serialized);
+        assert!(first.serialized != signed(None).serialized);
+        for result in [first, second] {
+            let token = Biscuit::from_base64(result.serialized.unwrap());
+            let verified = verify_token_inner(token, std::slice::from_ref(&keypair.public_key), "allow if a(1);");
+            assert!(matches!(verified.verifier_result.unwrap().status, VerifierStatus::Success { .. }));
+        }
+
+        // a seed without a private key still gives the same token every time
+        let seeded = || execute_inner(BiscuitQuery { root_seed: Some(3), ..query(&["a(1);", "b(2);"], "allow if true;") });
+        assert!(seeded().serialized.is_some());
+        assert!(seeded().serialized == seeded().serialized);
+    }
+
+    #[test]
+    fn attenuation_blocks_are_not_predictable() {
+        let token = execute_inner(query(&["a(1);"], "allow if true;")).serialized.unwrap();
+        let attenuated = || attenuate_inner(&token, &["check if a(1);".to_string()]).serialized;
+
+        let first = attenuated();
+        assert!(first.is_some());
+        assert!(first != attenuated());
+    }
+
+    fn rule_errors(editor: &Editor) -> usize {
+        editor.errors.iter().filter(|error| error.code == ErrorCode::RuleWithoutPredicates).count()
+    }
+
+    #[test]
+    fn rules_without_predicates_in_token_blocks() {
+        let result = execute_inner(query(&["b(#authority, 1) <- 1 == 1;", "c(1) <- true;"], "allow if true;"));
+
+        assert_eq!(rule_errors(&result.token_blocks[0]), 1);
+        assert_eq!(rule_errors(&result.token_blocks[1]), 1);
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::Success { .. }));
+
+        let token = execute_inner(query(&["a(1);"], "")).serialized.unwrap();
+        let attenuated = attenuate_inner(&token, &["c(1) <- true;".to_string()]);
+        assert_eq!(rule_errors(&attenuated.token_blocks[0]), 1);
+    }
+
+    #[test]
+    fn rules_without_predicates_in_verifiers() {
+        let result = execute_inner(BiscuitQuery {
+            scenarios: Some(vec![VerifierScenario {
+                name: "scenario".to_string(),
+                verifier_code: "c(1) <- true; allow if true;".to_string(),
+                ambient: AmbientContext::default(),
+            }]),
+            ..query(&["a(1);"], "b(1) <- 1 == 1; allow if true;")
+        });
+        assert_eq!(rule_errors(result.verifier_editor.as_ref().unwrap()), 1);
+        let scenario = &result.scenario_results[0];
+        assert!(scenario.passed);
+        assert_eq!(rule_errors(scenario.verifier_editor.as_ref().unwrap()), 1);
+
+        let result = run_authorizer_inner("b(1) <- 1 == 1; allow if true;");
+        assert_eq!(rule_errors(&result.verifier_editor), 1);
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::Success { .. }));
+    }
+
+    #[test]
+    fn rules_without_predicates_in_snapshots() {
+        let mut verifier = Verifier::new().unwrap();
+        verifier.add_rule(builder::Rule::try_from("b(1) <- 1 == 1").unwrap()).unwrap();
+        verifier.add_policy("allow if true").unwrap();
+        let snapshot = base64::encode_config(&verifier.save().unwrap()[..], base64::URL_SAFE);
+
+        let result = restore_verifier_snapshot_inner(&snapshot);
+        assert_eq!(rule_errors(&result.verifier_editor), 1);
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::Success { .. }));
+    }
+
+    #[test]
+    fn rules_without_predicates_in_loaded_tokens() {
+        let root = KeyPair::new_with_rng(&mut StdRng::seed_from_u64(0));
+        let mut builder = Biscuit::builder(&root);
+        builder.add_authority_rule(builder::Rule::try_from("b(#authority, 1) <- 1 == 1").unwrap()).unwrap();
+        let token = builder.build_with_rng(&mut StdRng::seed_from_u64(0)).unwrap();
+
+        let result = verify_token_inner(Ok(token), &[hex::encode(root.public().to_bytes())], "allow if true;");
+        assert!(result.error.unwrap().starts_with(RULE_WITHOUT_PREDICATES));
+        assert!(result.verifier_result.is_none());
+    }
+
+    #[test]
+    fn rules_without_predicates_in_derivations() {
+        let rule = builder::Rule::try_from("b(1) <- 1 == 1").unwrap();
+        let derivations = get_rule_derivations(
+            &mut Verifier::new().unwrap(),
+            &[(SourcePosition::new(0, 0, 0, 14), rule)],
+            MarkerSource::Verifier,
+            &VerifierLimitsInput::default().to_limits(),
+        );
+
+        assert_eq!(derivations[0].error.as_deref(), Some(RULE_WITHOUT_PREDICATES));
+        assert!(derivations[0].facts.is_empty());
+    }
+
+    #[test]
+    fn recursive_query_within_the_session_limits() {
+        let edges = (0..30).map(|i| format!("edge({}, {});", i, i + 1)).collect::<Vec<_>>().join("\n");
+        let program = format!("{}\npath($x, $y) <- edge($x, $y);\npath($x, $z) <- edge($x, $y), path($y, $z);", edges);
+        let datalog = "reachable($y) <- path(0, $y)";
+        let run = |max_iterations: u32| execute_inner(BiscuitQuery {
+            query: Some(datalog.to_string()),
+            queries: Some(vec![NamedQuery {
+                name: "reachable".to_string(),
+                datalog: datalog.to_string(),
+                params: HashMap::new(),
+            }]),
+            verifier_limits: Some(VerifierLimitsInput {
+                max_facts: Some(100_000),
+                max_iterations: Some(max_iterations),
+                max_time_millis: Some(0),
+            }),
+            ..query(&[&program], "allow if true;")
+        });
+
+        let result = run(1_000);
+        assert_eq!(result.query_result.len(), 30);
+        assert_eq!(result.query_results[0].facts.len(), 30);
+
+        let result = run(10);
+        assert!(result.query_error.unwrap().contains("TooManyIterations"));
+        assert!(result.query_editor.is_none());
+        assert!(result.query_results[0].error.as_ref().unwrap().contains("TooManyIterations"));
+    }
+
+    #[test]
+    fn failed_check_explanations_use_the_session_limits() {
+        let mut verifier = Verifier::new().unwrap();
+        for i in 0..30 {
+            verifier.add_fact(format!("edge({}, {})", i, i + 1).as_str()).unwrap();
+        }
+        verifier.add_rule("path($x, $y) <- edge($x, $y)").unwrap();
+        verifier.add_rule("path($x, $z) <- edge($x, $y), path($y, $z)").unwrap();
+        let explain = |max_iterations: u32| {
+            let mut error = VerifierError {
+                rule: Some("check if path(0, $y), $y > 30".to_string()),
+                ..VerifierError::new("failed_check")
+            };
+            let limits = VerifierLimitsInput {
+                max_facts: Some(100_000),
+                max_iterations: Some(max_iterations),
+                max_time_millis: Some(0),
+            }.to_limits();
+            explain_failed_check(&mut verifier.clone(), &mut error, &limits);
+            error
+        };
+
+        let error = explain(10);
+        assert!(error.explanation_error.unwrap().contains("TooManyIterations"));
+        assert!(error.bindings.is_empty() && error.failing_expressions.is_empty());
+
+        let error = explain(1_000);
+        assert!(error.explanation_error.is_none());
+        assert_eq!(error.failing_expressions, ["$y > 30"]);
+        assert_eq!(error.bindings.len(), MAX_BINDINGS);
+    }
+
+    #[test]
+    fn integers_beyond_the_js_range() {
+        let result = execute_inner(BiscuitQuery {
+            query: Some("b($i) <- a($i)".to_string()),
+            ..query(&["a(9223372036854775807);", "a(-9007199254740991);"], "allow if true;")
+        });
+
+        let values = result.query_result.iter()
+            .map(|fact| serde_json::to_value(&fact.terms[0]).unwrap()["value"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(values, [serde_json::json!(-9007199254740991i64), serde_json::json!("9223372036854775807")]);
+
+        let term: TermValue = serde_json::from_value(serde_json::json!({ "type": "int", "value": "9223372036854775807" })).unwrap();
+        assert!(matches!(term, TermValue::Int(i64::MAX)));
+    }
+
+    #[test]
+    fn world_facts_define_predicates() {
+        let result = execute_inner(BiscuitQuery {
+            world_facts_json: Some(vec![FactInput {
+                name: "user".to_string(),
+                terms: vec![TermValue::String("alice".to_string())],
+            }]),
+            ..query(&["a(1);"], "allow if user(\"alice\");")
+        });
+
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::Success { .. }));
+        assert!(result.verifier_editor.unwrap().warnings.is_empty());
+        assert!(result.defined_predicates.contains(&"user".to_string()));
+    }
+
+    #[test]
+    fn unclosed_comment_in_a_verifier_snippet() {
+        let result = execute_inner(BiscuitQuery {
+            verifier_blocks: Some(vec![
+                "allow if a(2); /* todo".to_string(),
+                "allow if a(1);".to_string(),
+            ]),
+            ..query(&["a(1);"], "")
+        });
+
+        assert_eq!(result.verifier_code.as_deref(), Some("allow if a(2); /* todo*/\nallow if a(1);\n"));
+        assert!(matches!(result.verifier_result.unwrap().status, VerifierStatus::Success { policy: 1 }));
+
+        let errors = &result.verifier_editors[0].errors;
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].code == ErrorCode::UnclosedComment && matches!(errors[0].severity, Severity::Error));
+        assert_eq!(position(&errors[0].position), (0, 15, 0, 22));
+        assert!(result.verifier_editors[0].warnings.is_empty());
+        assert!(result.verifier_editors[1].errors.is_empty());
+        assert_eq!(result.verifier_editors[1].elements.len(), 1);
+    }
+
+    #[test]
+    fn rendered_expressions_parse_back() {
+        let source = r#"check if s($s), $s == "a\"b", $s.starts_with("a\\"), $t <= 2021-01-01T00:00:00+00:00"#;
+        let check = builder::Check::try_from(source).unwrap();
+
+        let code = check_code(&check);
+        assert_eq!(code, source);
+        assert_eq!(builder::Check::try_from(code.as_str()).unwrap(), check);
+    }
+
+    #[test]
+    fn size_and_fingerprint_of_sealed_tokens() {
+        let run = |seal| execute_inner(BiscuitQuery {
+            seal: Some(seal),
+            seal_secret: Some("secret".to_string()),
+            root_seed: Some(0),
+            ..query(&["a(1);"], "allow if true;")
+        });
+
+        for result in [run(false), run(true)] {
+            let bytes = base64::decode_config(result.serialized.unwrap(), base64::URL_SAFE).unwrap();
+            assert_eq!(result.token_size, bytes.len());
+            assert_eq!(result.token_fingerprint.unwrap(), hex::encode(Sha256::digest(&bytes)));
+        }
+        assert!(run(false).token_size != run(true).token_size);
+    }
 }